@@ -0,0 +1,97 @@
+use bollard::Docker;
+use bollard::errors::Error::DockerResponseServerError;
+use bollard::models::HealthStatusEnum;
+use bollard::query_parameters::{
+    InspectContainerOptions, ListContainersOptionsBuilder, RemoveContainerOptionsBuilder, StopContainerOptions,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Runtime;
+
+/// Label of every container the harness starts, its value is the id of the run that started it.
+pub const RUN_LABEL: &str = "bachelor_thesis.run";
+
+/// Id of this run, unique across runs on the same host so their containers never collide.
+pub fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        format!("{started}-{}", std::process::id())
+    })
+}
+
+/// Name of a container of this run.
+pub fn container_name(prefix: &str) -> String {
+    format!("{prefix}.{}", run_id())
+}
+
+/// Labels that mark a container as started by this run.
+pub fn labels() -> HashMap<String, String> {
+    HashMap::from([(RUN_LABEL.to_string(), run_id().to_string())])
+}
+
+/// Host port Docker published the container port on, for containers started without a fixed host port.
+pub async fn host_port(docker: &Docker, id: &str, port: &str) -> Result<u16, Box<dyn Error>> {
+    let container = docker.inspect_container(id, None::<InspectContainerOptions>).await?;
+    container
+        .network_settings
+        .and_then(|settings| settings.ports)
+        .and_then(|mut ports| ports.remove(port).flatten())
+        .and_then(|bindings| bindings.into_iter().find_map(|binding| binding.host_port))
+        .ok_or_else(|| format!("container {id} doesn't publish port {port}"))?
+        .parse()
+        .map_err(|e| format!("container {id} published port {port} on an invalid host port: {e}").into())
+}
+
+/// Waits until the health check of the container passes. Fails once it reports unhealthy, the container exits
+/// or the timeout is over.
+pub async fn wait_until_healthy(docker: &Docker, id: &str, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    loop {
+        let state = docker.inspect_container(id, None::<InspectContainerOptions>).await?.state.unwrap_or_default();
+        match state.health.and_then(|health| health.status) {
+            Some(HealthStatusEnum::HEALTHY) => return Ok(()),
+            Some(HealthStatusEnum::UNHEALTHY) => return Err(format!("container {id} is unhealthy").into()),
+            _ if state.running == Some(false) => return Err(format!("container {id} exited while starting").into()),
+            _ if start.elapsed() > timeout => {
+                return Err(format!("container {id} wasn't healthy after {} s", timeout.as_secs()).into());
+            }
+            _ => tokio::time::sleep(Duration::from_millis(500)).await,
+        }
+    }
+}
+
+/// Stops and removes a container along with its anonymous volumes. A container that is already stopped or gone
+/// counts as removed, so teardown can run more than once.
+pub async fn remove(docker: &Docker, id: &str) -> Result<(), Box<dyn Error>> {
+    match docker.stop_container(id, None::<StopContainerOptions>).await {
+        // 304 is an already stopped container
+        Ok(()) | Err(DockerResponseServerError { status_code: 304, .. }) => (),
+        Err(DockerResponseServerError { status_code: 404, .. }) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    let options = RemoveContainerOptionsBuilder::default().force(true).v(true).build();
+    match docker.remove_container(id, Some(options)).await {
+        Ok(()) | Err(DockerResponseServerError { status_code: 404, .. }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Removes the containers of all runs, including ones a crashed run left behind, and returns their names.
+pub fn remove_stale() -> Result<Vec<String>, Box<dyn Error>> {
+    let rt = Runtime::new()?;
+    rt.block_on(async {
+        let docker = Docker::connect_with_defaults()?;
+        let filters = HashMap::from([("label", vec![RUN_LABEL])]);
+        let options = ListContainersOptionsBuilder::default().all(true).filters(&filters).build();
+        let mut removed = Vec::new();
+        for container in docker.list_containers(Some(options)).await? {
+            let Some(id) = container.id else { continue };
+            remove(&docker, &id).await?;
+            removed.push(container.names.and_then(|names| names.into_iter().next()).unwrap_or(id));
+        }
+        Ok(removed)
+    })
+}
@@ -0,0 +1,369 @@
+/// Part of a query, parentheses group their content so function arguments and operands can be found.
+#[derive(Clone, Debug)]
+enum Node {
+    Space(String),
+    /// Identifiers, keywords and numbers, including qualified names like `Authors.name`.
+    Word(String),
+    /// String literals and quoted identifiers, kept verbatim.
+    Quoted(String),
+    /// Operators and punctuation.
+    Op(String),
+    Group(Vec<Node>),
+}
+
+/// PostGIS functions that read geometries from text and take an optional SRID as their last argument.
+const FROM_TEXT_FUNCTIONS: [&str; 7] = [
+    "st_geomfromtext",
+    "st_geometryfromtext",
+    "st_polygonfromtext",
+    "st_linefromtext",
+    "st_pointfromtext",
+    "st_mpolyfromtext",
+    "st_mlinefromtext",
+];
+
+/// Derives the DuckDB version of a Postgres query. Covers the differences the benchmark queries run into:
+/// regular expression operators, Postgres only cast types and the PostGIS functions that DuckDB spatial
+/// names or parameterizes differently. Anything else is passed through unchanged.
+pub fn postgres_to_duckdb(query: &str) -> String {
+    let mut chars = query.chars().peekable();
+    match parse(&mut chars) {
+        Some(nodes) if chars.peek().is_none() => render(&rewrite(nodes)),
+        // Unbalanced parentheses, leave the query to DuckDB's own error message
+        _ => query.to_string(),
+    }
+}
+
+/// Reads nodes up to the closing parenthesis of the current group, None if a nested group isn't closed.
+fn parse(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Vec<Node>> {
+    let mut nodes = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if c == ')' {
+            break;
+        }
+        chars.next();
+        let node = match c {
+            // Comments are dropped, so quotes in them can't start a string literal
+            '-' if chars.next_if_eq(&'-').is_some() => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                Node::Space(String::from(" "))
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                skip_block_comment(chars)?;
+                Node::Space(String::from(" "))
+            }
+            '(' => {
+                let group = parse(chars)?;
+                chars.next_if_eq(&')')?;
+                Node::Group(group)
+            }
+            '\'' | '"' => {
+                let mut text = String::from(c);
+                while let Some(next) = chars.next() {
+                    text.push(next);
+                    // A doubled quote is an escaped one
+                    if next == c && chars.next_if_eq(&c).map(|quote| text.push(quote)).is_none() {
+                        break;
+                    }
+                }
+                Node::Quoted(text)
+            }
+            c if c.is_whitespace() => Node::Space(take_while(c, chars, char::is_whitespace)),
+            c if c.is_alphanumeric() || c == '_' => {
+                Node::Word(take_while(c, chars, |c| c.is_alphanumeric() || matches!(c, '_' | '.' | '$')))
+            }
+            ',' | ';' | '[' | ']' => Node::Op(c.to_string()),
+            c => {
+                let mut op = String::from(c);
+                // Like in Postgres, an operator ends where a comment starts
+                while !starts_comment(chars)
+                    && let Some(next) = chars.next_if(|c| "~!@#%^&|`?<>=+-*/:".contains(*c))
+                {
+                    op.push(next);
+                }
+                Node::Op(op)
+            }
+        };
+        nodes.push(node);
+    }
+    Some(nodes)
+}
+
+fn starts_comment(chars: &std::iter::Peekable<std::str::Chars>) -> bool {
+    let mut ahead = chars.clone();
+    matches!((ahead.next(), ahead.next()), (Some('-'), Some('-')) | (Some('/'), Some('*')))
+}
+
+/// Skips the rest of a block comment, which Postgres lets nest. None if it isn't closed.
+fn skip_block_comment(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<()> {
+    let mut depth = 1;
+    while depth > 0 {
+        match chars.next()? {
+            '*' if chars.next_if_eq(&'/').is_some() => depth -= 1,
+            '/' if chars.next_if_eq(&'*').is_some() => depth += 1,
+            _ => (),
+        }
+    }
+    Some(())
+}
+
+fn take_while(first: char, chars: &mut std::iter::Peekable<std::str::Chars>, accept: impl Fn(char) -> bool) -> String {
+    let mut text = String::from(first);
+    while let Some(c) = chars.next_if(|c| accept(*c)) {
+        text.push(c);
+    }
+    text
+}
+
+fn render(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node {
+            Node::Space(value) | Node::Word(value) | Node::Quoted(value) | Node::Op(value) => text += value,
+            Node::Group(children) => text += &format!("({})", render(children)),
+        }
+    }
+    text
+}
+
+/// Rewrites function calls first, then the operators between the rewritten operands.
+fn rewrite(nodes: Vec<Node>) -> Vec<Node> {
+    let mut calls = Vec::new();
+    let mut nodes = nodes.into_iter().peekable();
+    while let Some(node) = nodes.next() {
+        match (node, nodes.peek()) {
+            (Node::Word(name), Some(Node::Group(_))) => {
+                let Some(Node::Group(arguments)) = nodes.next() else { unreachable!() };
+                calls.extend(rewrite_call(name, arguments));
+            }
+            (Node::Group(children), _) => calls.push(Node::Group(rewrite(children))),
+            (node, _) => calls.push(node),
+        }
+    }
+    rewrite_operators(calls)
+}
+
+fn rewrite_call(name: String, arguments: Vec<Node>) -> Vec<Node> {
+    let original = split_arguments(&arguments);
+    let lower = name.to_lowercase();
+    let call = |name: &str, arguments: Vec<String>| vec![Node::Word(format!("{name}({})", arguments.join(", ")))];
+    let rewritten = || original.iter().map(|argument| render(&rewrite(argument.clone()))).collect::<Vec<String>>();
+    match lower.as_str() {
+        // DuckDB geometries carry no SRID, so it is dropped and only used to resolve transformations
+        function if FROM_TEXT_FUNCTIONS.contains(&function) => {
+            call("ST_GeomFromText", rewritten().into_iter().take(1).collect())
+        }
+        "st_setsrid" if original.len() == 2 => rewrite(original[0].clone()),
+        "st_makepoint" => call("ST_Point", rewritten()),
+        // DuckDB needs the source projection, which PostGIS takes from the geometry. PostGIS always
+        // treats x as the longitude, DuckDB only with always_xy
+        "st_transform" if original.len() == 2 => match srid_of(&original[0]) {
+            Some(source) => {
+                let arguments = rewritten();
+                let target = format!("'EPSG:{}'", arguments[1]);
+                call(&name, vec![arguments[0].clone(), format!("'EPSG:{source}'"), target, String::from("true")])
+            }
+            None => vec![Node::Word(name), Node::Group(rewrite(arguments))],
+        },
+        _ => vec![Node::Word(name), Node::Group(rewrite(arguments))],
+    }
+}
+
+/// SRID a geometry expression was created with, if it is given explicitly.
+fn srid_of(expression: &[Node]) -> Option<String> {
+    let nodes: Vec<&Node> = expression.iter().filter(|node| !matches!(node, Node::Space(_))).collect();
+    let [Node::Word(name), Node::Group(arguments)] = nodes.as_slice() else { return None };
+    let lower = name.to_lowercase();
+    if !FROM_TEXT_FUNCTIONS.contains(&lower.as_str()) && lower != "st_setsrid" {
+        return None;
+    }
+    let arguments = split_arguments(arguments);
+    match arguments.as_slice() {
+        [_, srid] => Some(render(srid).trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Splits the content of a call at its top level commas, trimming the whitespace around each argument.
+fn split_arguments(nodes: &[Node]) -> Vec<Vec<Node>> {
+    let mut arguments = vec![Vec::new()];
+    for node in nodes {
+        match node {
+            Node::Op(op) if op == "," => arguments.push(Vec::new()),
+            node => arguments.last_mut().unwrap().push(node.clone()),
+        }
+    }
+    for argument in arguments.iter_mut() {
+        while matches!(argument.first(), Some(Node::Space(_))) {
+            argument.remove(0);
+        }
+        while matches!(argument.last(), Some(Node::Space(_))) {
+            argument.pop();
+        }
+    }
+    arguments
+}
+
+fn rewrite_operators(mut nodes: Vec<Node>) -> Vec<Node> {
+    let mut index = 0;
+    while index < nodes.len() {
+        match &nodes[index] {
+            Node::Op(op) if matches!(op.as_str(), "~" | "~*" | "!~" | "!~*") => {
+                let (Some(start), Some(end)) = (operand_start(&nodes, index), operand_end(&nodes, index)) else {
+                    index += 1;
+                    continue;
+                };
+                // Postgres matches anywhere in the string, while DuckDB's ~ has to match the whole string
+                let negated = if op.starts_with('!') { "NOT " } else { "" };
+                let flags = if op.ends_with('*') { ", 'i'" } else { "" };
+                let left = render(&nodes[start..index]).trim().to_string();
+                let right = render(&nodes[index + 1..=end]).trim().to_string();
+                let call = Node::Word(format!("{negated}regexp_matches({left}, {right}{flags})"));
+                nodes.splice(start..=end, [call]);
+                index = start + 1;
+            }
+            Node::Op(op) if op == "::" => {
+                if let Some(Node::Word(name)) = nodes.get_mut(index + 1)
+                    && let Some(duckdb) = cast_type(name)
+                {
+                    *name = duckdb.to_string();
+                }
+                index += 1;
+            }
+            _ => index += 1,
+        }
+    }
+    nodes
+}
+
+/// Cast types Postgres has and DuckDB doesn't, mapped to their closest DuckDB type.
+fn cast_type(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "jsonb" => Some("JSON"),
+        "bpchar" | "citext" | "name" => Some("VARCHAR"),
+        "int2" => Some("SMALLINT"),
+        "float4" => Some("REAL"),
+        "bytea" => Some("BLOB"),
+        _ => None,
+    }
+}
+
+/// Whether the operator joins the values of an operand: a cast, or a concatenation, which binds tighter
+/// than the regular expression operators.
+fn joins_operand(node: &Node) -> bool {
+    matches!(node, Node::Op(op) if op == "::" || op == "||")
+}
+
+/// First node of the operand left of the operator at `index`, values or calls followed by any casts and
+/// concatenated with `||`.
+fn operand_start(nodes: &[Node], index: usize) -> Option<usize> {
+    let mut start = previous(nodes, index)?;
+    loop {
+        if matches!(nodes[start], Node::Group(_)) && start > 0 && matches!(nodes[start - 1], Node::Word(_)) {
+            start -= 1;
+        }
+        match previous(nodes, start) {
+            Some(join) if joins_operand(&nodes[join]) => start = previous(nodes, join)?,
+            _ => return Some(start),
+        }
+    }
+}
+
+/// Last node of the operand right of the operator at `index`, values or calls followed by any casts and
+/// concatenated with `||`.
+fn operand_end(nodes: &[Node], index: usize) -> Option<usize> {
+    let mut end = next(nodes, index)?;
+    loop {
+        if matches!(nodes[end], Node::Word(_)) && matches!(nodes.get(end + 1), Some(Node::Group(_))) {
+            end += 1;
+        }
+        match next(nodes, end) {
+            Some(join) if joins_operand(&nodes[join]) => end = next(nodes, join)?,
+            _ => return Some(end),
+        }
+    }
+}
+
+fn previous(nodes: &[Node], index: usize) -> Option<usize> {
+    (0..index).rev().find(|index| !matches!(nodes[*index], Node::Space(_)))
+}
+
+fn next(nodes: &[Node], index: usize) -> Option<usize> {
+    (index + 1..nodes.len()).find(|index| !matches!(nodes[*index], Node::Space(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_regex_operators() {
+        assert_eq!(
+            postgres_to_duckdb("SELECT * FROM Authors WHERE name ~ '^A'"),
+            "SELECT * FROM Authors WHERE regexp_matches(name, '^A')"
+        );
+        assert_eq!(postgres_to_duckdb("WHERE name ~* 'smith'"), "WHERE regexp_matches(name, 'smith', 'i')");
+        assert_eq!(postgres_to_duckdb("WHERE name !~ 'x'"), "WHERE NOT regexp_matches(name, 'x')");
+        assert_eq!(postgres_to_duckdb("WHERE name !~* 'x'"), "WHERE NOT regexp_matches(name, 'x', 'i')");
+        assert_eq!(
+            postgres_to_duckdb("WHERE lower(a.name) ~ lower(p.title) AND year > 2000"),
+            "WHERE regexp_matches(lower(a.name), lower(p.title)) AND year > 2000"
+        );
+        assert_eq!(postgres_to_duckdb("WHERE (title ~ 'x')"), "WHERE (regexp_matches(title, 'x'))");
+    }
+
+    #[test]
+    fn keeps_concatenations_in_regex_operands() {
+        assert_eq!(postgres_to_duckdb("WHERE name ~ 'a' || 'b'"), "WHERE regexp_matches(name, 'a' || 'b')");
+        assert_eq!(
+            postgres_to_duckdb("WHERE title ~* '^' || v.name || '$' AND year = 2020"),
+            "WHERE regexp_matches(title, '^' || v.name || '$', 'i') AND year = 2020"
+        );
+        assert_eq!(
+            postgres_to_duckdb("WHERE first || ' ' || last ~ 'Smith'"),
+            "WHERE regexp_matches(first || ' ' || last, 'Smith')"
+        );
+    }
+
+    #[test]
+    fn rewrites_casts() {
+        assert_eq!(postgres_to_duckdb("SELECT tags::jsonb, name::citext"), "SELECT tags::JSON, name::VARCHAR");
+        assert_eq!(postgres_to_duckdb("SELECT x::int2, y::float4, z::bytea"), "SELECT x::SMALLINT, y::REAL, z::BLOB");
+        assert_eq!(postgres_to_duckdb("SELECT year::text"), "SELECT year::text");
+        assert_eq!(postgres_to_duckdb("WHERE key::bpchar ~ 'conf/'"), "WHERE regexp_matches(key::VARCHAR, 'conf/')");
+    }
+
+    #[test]
+    fn rewrites_spatial_functions() {
+        assert_eq!(
+            postgres_to_duckdb("SELECT ST_GeomFromText('POINT(7.8 48)', 4326)"),
+            "SELECT ST_GeomFromText('POINT(7.8 48)')"
+        );
+        assert_eq!(postgres_to_duckdb("SELECT ST_SetSRID(ST_MakePoint(7.8, 48), 4326)"), "SELECT ST_Point(7.8, 48)");
+        assert_eq!(
+            postgres_to_duckdb("SELECT ST_Transform(ST_SetSRID(ST_MakePoint(lon, lat), 4326), 3857) FROM osm"),
+            "SELECT ST_Transform(ST_Point(lon, lat), 'EPSG:4326', 'EPSG:3857', true) FROM osm"
+        );
+        // Without a known source projection the call is left to DuckDB
+        assert_eq!(postgres_to_duckdb("SELECT ST_Transform(geom, 3857)"), "SELECT ST_Transform(geom, 3857)");
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(
+            postgres_to_duckdb("SELECT name -- the author's name\nFROM Authors WHERE name ~ 'A'"),
+            "SELECT name  \nFROM Authors WHERE regexp_matches(name, 'A')"
+        );
+        assert_eq!(
+            postgres_to_duckdb("SELECT /* don't /* nested */ count */ x::jsonb"),
+            "SELECT   x::JSON"
+        );
+        assert_eq!(postgres_to_duckdb("WHERE a ~--'x'\n'y'"), "WHERE regexp_matches(a, 'y')");
+    }
+
+    #[test]
+    fn leaves_unbalanced_queries_unchanged() {
+        assert_eq!(postgres_to_duckdb("SELECT (name ~ 'a'"), "SELECT (name ~ 'a'");
+        assert_eq!(postgres_to_duckdb("SELECT 1 /* open"), "SELECT 1 /* open");
+    }
+}
@@ -1,9 +1,15 @@
-use crate::{AFFILIATIONS_FILE, ALIAS_FILE, AUTHOR_FILE, AUTHOR_WEBSITES_FILE, EDITOR_FILE, PUBLICATION_AUTHORS_FILE, PUBLICATION_EDITOR_FILE, PUBLICATION_FILE, PUBLISHER_FILE, REFERENCE_FILE, RESOURCES_FILE, VENUE_FILE};
+use crate::formats::OutputFormat;
+use crate::plans::{visit_nodes, QueryPlan};
+use crate::incremental::{change_file, delete_sql, staging_sql, update_sql, ChangeKind};
+use crate::schemas::{Deferred, SchemaVariant};
+use crate::tables::{dblp_tables, file_in};
+use crate::{RunConfig, SetupRecord, CHANGES_DIR, DATA_DIR, PREVIOUS_DIR};
 use duckdb::{params, Connection};
+use serde_json::Value;
 use std::error::Error;
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::time::Instant;
 
 pub struct DuckDBConnection {
@@ -12,14 +18,20 @@ pub struct DuckDBConnection {
 }
 
 impl DuckDBConnection {
-    pub fn new(dataset: &String) -> Result<DuckDBConnection,  Box<dyn Error >> {
+    pub fn new(dataset: &str, config: &RunConfig) -> Result<DuckDBConnection,  Box<dyn Error >> {
         let dataset_parts: Vec<&str> = dataset.split(" ").collect();
         let mut conn = DuckDBConnection { connection: Connection::open("db.duckdb").unwrap(), dataset: dataset .to_string() };
         // TODO Add more datasets
         match dataset_parts[0] {
             "dblp" => {
-                conn.create_tables_dblp();
-                conn.insert_dblp_data();
+                conn.create_tables_dblp(config.schema);
+                if config.incremental {
+                    conn.insert_dblp_data(PREVIOUS_DIR, config.format)?;
+                    conn.apply_dblp_changes(CHANGES_DIR)?;
+                } else {
+                    conn.insert_dblp_data(DATA_DIR, config.format)?;
+                }
+                conn.transform_dblp(config.schema);
             },
             "osm-country" => {
                 conn.load_spatial_module();
@@ -27,6 +39,7 @@ impl DuckDBConnection {
             }
             _ => { return Err("dataset could not be resolved for duckdb Connection".into())}
         }
+        conn.post_load(&config.setup)?;
 
         Ok(conn)
     }
@@ -38,62 +51,117 @@ impl DuckDBConnection {
 
     fn load_osm_country_data(&mut self) {
         let dataset_parts: Vec<&str> = self.dataset.split(" ").collect();
-        let continent = dataset_parts[1];
         let country = dataset_parts[2];
         let file_path = format!("/data/{country}-latest.osm.pbf");
         let query = format!("CREATE TABLE osm AS SELECT * FROM ST_ReadOSM('{file_path}');");
         self.connection.execute(&query, []).unwrap();
     }
 
-    pub fn create_tables_dblp(&mut self) {
-        let mut file = File::open("create_tables_dblp.sql").unwrap();
-        let mut query = String::new();
-        file.read_to_string(&mut query).unwrap();
-        query = format!("BEGIN;\n {}\n COMMIT;", query);
+    pub fn create_tables_dblp(&mut self, schema: SchemaVariant) {
+        let query = format!("BEGIN;\n {}\n COMMIT;", schema.create_sql(Deferred::default()));
         self.connection.execute_batch(&query).unwrap();
         println!("Created Tables DBLP");
     }
 
-    pub fn insert_dblp_data(&mut self) {
-        let query = format!(
-            "BEGIN;\n\
-             COPY Venues FROM '{0}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Publishers FROM '{1}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Editors FROM '{2}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Authors FROM '{3}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Publications FROM '{4}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Resources FROM '{5}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY PublicationEditors FROM '{6}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Reference FROM '{7}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY PublicationAuthors FROM '{8}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY AuthorWebsites FROM '{9}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Affiliations FROM '{10}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             COPY Alias FROM '{11}' (FORMAT CSV, DELIMITER E'\\t', HEADER true);\n\
-             END;",
-            VENUE_FILE,
-            PUBLISHER_FILE,
-            EDITOR_FILE,
-            AUTHOR_FILE,
-            PUBLICATION_FILE,
-            RESOURCES_FILE,
-            PUBLICATION_EDITOR_FILE,
-            REFERENCE_FILE,
-            PUBLICATION_AUTHORS_FILE,
-            AUTHOR_WEBSITES_FILE,
-            AFFILIATIONS_FILE,
-            ALIAS_FILE
-        );
-        self.connection.execute_batch(&query).unwrap();
+    /// Reshapes the loaded DBLP tables into the schema variant.
+    pub fn transform_dblp(&mut self, schema: SchemaVariant) {
+        let query = schema.transform_sql();
+        if query.is_empty() {
+            return;
+        }
+        self.connection.execute_batch(&format!("BEGIN;\n{query}COMMIT;")).unwrap();
+        println!("Transformed DBLP data into the {schema:?} schema");
+    }
+
+    /// Loads the DBLP tables parsed into `dir`.
+    pub fn insert_dblp_data(&mut self, dir: &str, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+        let options = copy_options(format)?;
+        let mut query = String::from("BEGIN;\n");
+        for table in dblp_tables() {
+            query += copy_sql(table.name, &table.columns, &file_in(dir, &format.file(table.file)), options).as_str();
+        }
+        query += "END;";
+        self.connection.execute_batch(&query)?;
         println!("Inserted DBLP data into DuckDB");
+        Ok(())
     }
 
-    pub fn run_test_query(&self, query: &str) -> u128 {
-        let mut stmt = self.connection.prepare(query).unwrap();
+    /// Applies the change sets of an incremental DBLP parse in `dir` and logs how long that took.
+    pub fn apply_dblp_changes(&mut self, dir: &str) -> Result<(), Box<dyn Error>> {
+        let tables = dblp_tables();
+        // DuckDB checks unique and foreign keys against rows deleted in the same transaction, so the deletes
+        // of every table are committed before the next table and before the replacement rows are inserted
+        let mut deletes = Vec::new();
+        // Owned rows go first, so no deleted record is still referenced
+        for table in tables.iter().rev() {
+            let Some(key) = table.changes.delete_key() else { continue };
+            let staging = format!("{}_deletes", table.name);
+            let mut query = String::from("BEGIN;\n");
+            query += staging_sql(table, &staging, &[key]).as_str();
+            query += copy_sql(&staging, &[key], &change_file(dir, table, ChangeKind::Delete), TSV_OPTIONS).as_str();
+            query += format!("{}\nDROP TABLE {staging};\nEND;", delete_sql(table, &staging, key)).as_str();
+            deletes.push(query);
+        }
+        // Tables go in foreign key order, so new venues and publishers exist before the records referencing them
+        let mut inserts = String::from("BEGIN;\n");
+        for table in tables.iter() {
+            if let Some(key) = table.changes.update_key() {
+                let staging = format!("{}_updates", table.name);
+                inserts += staging_sql(table, &staging, &table.columns).as_str();
+                inserts += copy_sql(&staging, &table.columns, &change_file(dir, table, ChangeKind::Update), TSV_OPTIONS).as_str();
+                inserts += format!("{}\nDROP TABLE {staging};\n", update_sql(table, &staging, key)).as_str();
+            }
+            inserts += copy_sql(table.name, &table.columns, &change_file(dir, table, ChangeKind::Insert), TSV_OPTIONS).as_str();
+        }
+        inserts += "END;";
         let now = Instant::now();
-        let _ = stmt.query(params![]).unwrap();
+        for query in deletes {
+            self.connection.execute_batch(&query)?;
+        }
+        self.connection.execute_batch(&inserts)?;
+        let duration = now.elapsed().as_millis();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/data/duckdb.{}.log", self.dataset))?;
+        let _ = file.write(format!("Applied DBLP changes\nDuration: {duration}\n").as_bytes());
+        println!("Applied DBLP changes to DuckDB in {duration} ms");
+        Ok(())
+    }
+
+    /// Runs the setup statements of the dataset and refreshes the optimizer statistics, logging how long each step took.
+    /// A failing statement fails the run, so it is never benchmarked without the index it should have created.
+    pub fn post_load(&mut self, setup: &[SetupRecord]) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        let mut log = String::new();
+        for record in setup.iter().filter(|record| !record.duckdb.is_empty()) {
+            let start = Instant::now();
+            self.connection
+                .execute_batch(&record.duckdb)
+                .map_err(|e| format!("Setup {} failed for DuckDB: {e}", record.name))?;
+            log += &format!("Setup: {}\nDuration: {}\n", record.name, start.elapsed().as_millis());
+        }
+        // DuckDB has no VACUUM of its own, a checkpoint writes the loaded data to the database file instead
+        let start = Instant::now();
+        self.connection.execute_batch("ANALYZE;\nCHECKPOINT;")?;
+        log += &format!("Setup: ANALYZE and CHECKPOINT\nDuration: {}\n", start.elapsed().as_millis());
+        let duration = now.elapsed().as_millis();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/data/duckdb.{}.log", self.dataset))
+            .unwrap();
+        let _ = file.write(format!("{log}Post-load setup\nDuration: {duration}\n").as_bytes());
+        println!("Finished post-load setup of DuckDB in {duration} ms");
+        Ok(())
+    }
+
+    pub fn run_test_query(&self, query: &str) -> Result<u128, Box<dyn Error>> {
+        let mut stmt = self.connection.prepare(query)?;
+        let now = Instant::now();
+        let _ = stmt.query(params![])?;
         let duration = now.elapsed().as_millis();
         let mut file = OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open(format!("/data/duckdb.{}.log", self.dataset))
@@ -101,12 +169,226 @@ impl DuckDBConnection {
         let _ = file.write(
             format!("Query: {0}\nDuration: {1}\nResult Size: Columns {2} Rows {3}", query, duration, stmt.column_count(), stmt.row_count()).as_bytes()
         );
-        duration
+        Ok(duration)
     }
     
+    /// Runs the query with JSON profiling and summarizes the profile: latency and CPU time in ms, table scans,
+    /// rows scanned, and the peak buffer memory and temporary directory size in bytes, where a non zero temporary
+    /// directory means operators spilled to disk.
+    pub fn explain(&self, query: &str) -> Result<QueryPlan, Box<dyn Error>> {
+        // EXPLAIN ANALYZE reports the operators but zeroes the query wide metrics, the profiling output has both
+        self.connection.execute_batch(&format!(
+            "SET enable_profiling = 'json'; SET profiling_output = '{PROFILE_FILE}';"
+        ))?;
+        let profiled = self.fetch_all(query);
+        let profile = fs::read_to_string(PROFILE_FILE);
+        self.connection.execute_batch("RESET enable_profiling; RESET profiling_output;")?;
+        let _ = fs::remove_file(PROFILE_FILE);
+        profiled?;
+        let plan: Value = serde_json::from_str(&profile?)?;
+        let mut table_scans = 0;
+        visit_nodes(&plan, "children", &mut |node| {
+            if node["operator_type"] == "TABLE_SCAN" {
+                table_scans += 1;
+            }
+        });
+        let seconds = |value: &Value| value.as_f64().map(|value| (value * 1000.0).to_string()).unwrap_or_default();
+        let figure = |value: &Value| value.as_u64().map(|value| value.to_string()).unwrap_or_default();
+        let summary = vec![
+            ("latency_ms", seconds(&plan["latency"])),
+            ("cpu_ms", seconds(&plan["cpu_time"])),
+            ("table_scans", table_scans.to_string()),
+            ("rows_scanned", figure(&plan["cumulative_rows_scanned"])),
+            ("peak_buffer_bytes", figure(&plan["system_peak_buffer_memory"])),
+            ("peak_temp_dir_bytes", figure(&plan["system_peak_temp_dir_size"])),
+        ];
+        Ok(QueryPlan { plan, summary })
+    }
+
+    /// Runs the query and reads its whole result, so every operator runs to completion.
+    fn fetch_all(&self, query: &str) -> duckdb::Result<()> {
+        let mut stmt = self.connection.prepare(query)?;
+        let mut rows = stmt.query(params![])?;
+        while rows.next()?.is_some() {}
+        Ok(())
+    }
+
     pub fn close(self) -> Result<(), Box<dyn Error>> {
         fs::remove_file("db.duckdb").unwrap();
         self.connection.close().expect("connection close failed");
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// File DuckDB writes the profile of a query to while its plan is captured.
+const PROFILE_FILE: &str = "/data/duckdb.profile.json";
+
+const TSV_OPTIONS: &str = "FORMAT CSV, DELIMITER E'\\t', HEADER true";
+
+/// COPY options that read the DBLP tables in the given format.
+fn copy_options(format: OutputFormat) -> Result<&'static str, Box<dyn Error>> {
+    match format {
+        OutputFormat::Tsv => Ok(TSV_OPTIONS),
+        OutputFormat::Csv => Ok("FORMAT CSV, HEADER true"),
+        OutputFormat::Parquet => Ok("FORMAT PARQUET"),
+        OutputFormat::PgBinary => Err("DuckDB can't load Postgres binary COPY files".into()),
+    }
+}
+
+fn copy_sql(table: &str, columns: &[&str], file: &str, options: &str) -> String {
+    format!("COPY {table} ({}) FROM '{file}' ({options});\n", columns.join(", "))
+}
+
+#[cfg(test)]
+impl DuckDBConnection {
+    /// Connection to an empty in-memory database for the DBLP dataset.
+    pub fn in_memory() -> DuckDBConnection {
+        DuckDBConnection { connection: Connection::open_in_memory().unwrap(), dataset: String::from("dblp") }
+    }
+
+    /// The sorted rows of a query with every value as text, so results of differently typed columns compare.
+    pub fn rows(&self, query: &str) -> Vec<Vec<Option<String>>> {
+        let query = format!("SELECT CAST(COLUMNS(*) AS VARCHAR) FROM ({}) AS result;", query.trim_end_matches(';'));
+        let mut rows: Vec<Vec<Option<String>>> = self
+            .connection
+            .prepare(&query)
+            .unwrap()
+            .query_map([], |row| (0..row.as_ref().column_count()).map(|index| row.get(index)).collect())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        rows.sort();
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{parse_fixture, scratch_dir};
+    use crate::incremental::diff_dblp;
+
+    /// Loads a parse of the fixture and checks that the values end up in the columns they belong to.
+    #[test]
+    fn loads_parsed_columns() {
+        let dir = scratch_dir("duckdb_load");
+        parse_fixture("dblp.xml", &dir, OutputFormat::Tsv, None);
+        let mut conn = DuckDBConnection::in_memory();
+        conn.create_tables_dblp(SchemaVariant::Normalized);
+        conn.insert_dblp_data(&dir, OutputFormat::Tsv).unwrap();
+
+        let authors: Vec<(String, i64, Option<String>, Option<String>)> = conn
+            .connection
+            .prepare("SELECT name, id, CAST(mdate AS VARCHAR), orcid FROM Authors ORDER BY name, id;")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            authors,
+            vec![
+                (String::from("Alice Smith"), 0, Some(String::from("2023-01-01")), Some(String::from("0000-0002-1825-0097"))),
+                (String::from("Bob Jones"), 1, Some(String::from("2022-05-05")), None),
+            ]
+        );
+
+        let alias: (String, String) = conn
+            .connection
+            .query_row("SELECT a.name, alias FROM Alias JOIN Authors a ON a.key = Alias.author_id;", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(alias, (String::from("Alice Smith"), String::from("Alice S. Smith")));
+
+        let (title, year, r#type, venue): (String, i64, String, String) = conn
+            .connection
+            .query_row(
+                "SELECT title, year, CAST(p.type AS VARCHAR), v.name FROM Publications p JOIN Venues v ON v.id = p.venue_id WHERE key = 'conf/y/Jones19';",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!((title.as_str(), year, r#type.as_str(), venue.as_str()), ("Another Paper.", 2019, "inproceedings", "Y"));
+
+        let authors_of: Vec<(i64, String, String)> = conn
+            .connection
+            .prepare(
+                "SELECT position, raw_name, a.name FROM PublicationAuthors pa JOIN Authors a ON a.key = pa.author_id \
+                 WHERE publication_key = 'conf/y/Jones19' ORDER BY position;",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            authors_of,
+            vec![
+                (1, String::from("Bob Jones 0001"), String::from("Bob Jones")),
+                (2, String::from("Alice S. Smith"), String::from("Alice Smith")),
+            ]
+        );
+    }
+
+    /// Applies the changes between two parses of the fixtures, which have to keep all foreign keys intact.
+    #[test]
+    fn applies_changes() {
+        let (previous, current, changes) = (scratch_dir("duckdb_previous"), scratch_dir("duckdb_current"), scratch_dir("duckdb_changes"));
+        parse_fixture("dblp.xml", &previous, OutputFormat::Tsv, None);
+        parse_fixture("dblp.next.xml", &current, OutputFormat::Tsv, Some(&previous));
+        diff_dblp(&current, &previous, &changes).unwrap();
+        let mut conn = DuckDBConnection::in_memory();
+        conn.create_tables_dblp(SchemaVariant::Normalized);
+        conn.insert_dblp_data(&previous, OutputFormat::Tsv).unwrap();
+        conn.apply_dblp_changes(&changes).unwrap();
+
+        let query = |sql: &str| -> Vec<String> {
+            conn.connection
+                .prepare(sql)
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(
+            query("SELECT key || ' ' || title || ' ' || coalesce(v.name, '') FROM Publications p LEFT JOIN Venues v ON v.id = p.venue_id ORDER BY key;"),
+            vec![
+                "conf/y/Jones19 Another Paper. Y",
+                "journals/x/Jones36 Early Work. J. X",
+                "journals/x/Smith20 A Revised Paper. J. W",
+                "journals/z/New24 Brand New. J. Z",
+            ]
+        );
+        assert_eq!(
+            query("SELECT origin_pub || ' ' || dest_pub FROM Reference ORDER BY origin_pub;"),
+            vec!["journals/x/Smith20 conf/y/Jones19"]
+        );
+        assert_eq!(
+            query("SELECT pa.raw_name FROM PublicationAuthors pa WHERE publication_key = 'journals/z/New24' ORDER BY position;"),
+            vec!["Dave New", "Alice Smith"]
+        );
+        assert_eq!(
+            query("SELECT affiliation FROM Affiliations ORDER BY affiliation;"),
+            vec!["ETH Zurich", "University of Freiburg, Germany"]
+        );
+    }
+
+    #[test]
+    fn fails_on_failing_setup() {
+        let mut conn = DuckDBConnection::in_memory();
+        conn.create_tables_dblp(SchemaVariant::Normalized);
+        let setup = |name: &str, duckdb: &str| SetupRecord {
+            dataset: String::from("dblp"),
+            name: name.to_string(),
+            schemas: String::new(),
+            extensions: String::new(),
+            postgresql: String::new(),
+            duckdb: duckdb.to_string(),
+        };
+        conn.post_load(&[setup("year", "CREATE INDEX year_idx ON Publications(year);")]).unwrap();
+        let error = conn.post_load(&[setup("typo", "CREATE INDEX typo_idx ON Publication(year);")]).unwrap_err();
+        assert!(error.to_string().starts_with("Setup typo failed for DuckDB"), "{error}");
+    }
+}
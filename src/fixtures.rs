@@ -0,0 +1,25 @@
+use crate::formats::OutputFormat;
+use crate::parser::{ParseMode, Parser};
+use std::fs;
+
+/// Path of a file in the test fixtures.
+pub fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Empty directory for the files of one test, created anew on every run.
+pub fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("bachelor_thesis.{name}.{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+/// Parses a DBLP fixture into `dir`, continuing the ids of the parse in `previous` if given.
+pub fn parse_fixture(name: &str, dir: &str, format: OutputFormat, previous: Option<&str>) {
+    let mut parser = Parser::new(&fixture(name), dir, ParseMode::Strict, format).unwrap();
+    if let Some(previous) = previous {
+        parser.seed_from(previous).unwrap();
+    }
+    parser.run().unwrap();
+}
@@ -0,0 +1,472 @@
+use arrow_array::{ArrayRef, Date32Array, Int32Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use csv::{Writer, WriterBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Rows buffered per Parquet row group.
+const PARQUET_BATCH_ROWS: usize = 65536;
+
+/// Signature, flags and header extension length that start a Postgres binary COPY file.
+const PGCOPY_HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+
+/// File format the parser writes the tables in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Tab separated text, the default.
+    Tsv,
+    /// Comma separated text.
+    Csv,
+    /// Parquet files, loaded by DuckDB without parsing text.
+    Parquet,
+    /// Postgres binary COPY files, loaded by Postgres without parsing text.
+    PgBinary,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 4] = ["tsv", "csv", "parquet", "pg-binary"];
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "tsv" => Some(OutputFormat::Tsv),
+            "csv" => Some(OutputFormat::Csv),
+            "parquet" => Some(OutputFormat::Parquet),
+            "pg-binary" => Some(OutputFormat::PgBinary),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::PgBinary => "pg-binary",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::PgBinary => "pgcopy",
+        }
+    }
+
+    /// Field delimiter of the text formats.
+    pub fn delimiter(&self) -> Option<u8> {
+        match self {
+            OutputFormat::Tsv => Some(b'\t'),
+            OutputFormat::Csv => Some(b','),
+            _ => None,
+        }
+    }
+
+    /// Path of a table file written in this format.
+    pub fn file(&self, file: &str) -> String {
+        Path::new(file).with_extension(self.extension()).to_string_lossy().into_owned()
+    }
+}
+
+/// Value type of a column, derived from its SQL definition.
+#[derive(Clone, Copy, Debug)]
+enum ColumnType {
+    Int,
+    BigInt,
+    Date,
+    Text,
+}
+
+impl ColumnType {
+    fn of(definition: &str) -> ColumnType {
+        match definition.split_whitespace().next() {
+            Some("INTEGER") | Some("INT") => ColumnType::Int,
+            Some("BIGINT") => ColumnType::BigInt,
+            Some("DATE") => ColumnType::Date,
+            _ => ColumnType::Text,
+        }
+    }
+}
+
+/// Writes the rows of one table in the chosen output format.
+pub enum Sink {
+    Delimited(Writer<File>),
+    Parquet(ParquetSink),
+    PgBinary(PgBinarySink),
+}
+
+impl Sink {
+    /// Creates the file of a table from its tsv path and its columns as (name, SQL definition).
+    pub fn new(format: OutputFormat, file: &str, columns: &[(&'static str, &'static str)]) -> io::Result<Sink> {
+        let path = format.file(file);
+        let sink = match format {
+            OutputFormat::Tsv | OutputFormat::Csv => {
+                let mut writer = WriterBuilder::new()
+                    .delimiter(format.delimiter().unwrap())
+                    .has_headers(false)
+                    .from_path(&path)
+                    .map_err(|e| io::Error::other(format!("{path}: {e}")))?;
+                writer.write_record(columns.iter().map(|(name, _)| name))?;
+                Sink::Delimited(writer)
+            }
+            OutputFormat::Parquet => Sink::Parquet(ParquetSink::new(&path, columns)?),
+            OutputFormat::PgBinary => Sink::PgBinary(PgBinarySink::new(&path, columns)?),
+        };
+        Ok(sink)
+    }
+
+    pub fn write<R: Serialize>(&mut self, row: &R) -> io::Result<()> {
+        match self {
+            Sink::Delimited(writer) => Ok(writer.serialize(row)?),
+            Sink::Parquet(sink) => {
+                let values = row_values(row, &sink.names)?;
+                sink.write(values)
+            }
+            Sink::PgBinary(sink) => {
+                let values = row_values(row, &sink.names)?;
+                sink.write(values)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Delimited(writer) => writer.flush(),
+            Sink::Parquet(sink) => sink.flush(),
+            Sink::PgBinary(sink) => sink.writer.flush(),
+        }
+    }
+
+    /// Flushes the remaining rows and writes the footer of formats that have one.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Delimited(writer) => writer.flush(),
+            Sink::Parquet(sink) => sink.finish(),
+            Sink::PgBinary(sink) => sink.finish(),
+        }
+    }
+}
+
+/// Field values of a row in column order, taken from its serde representation.
+fn row_values<R: Serialize>(row: &R, names: &[&'static str]) -> io::Result<Vec<Value>> {
+    let Value::Object(mut fields) = serde_json::to_value(row)? else {
+        panic!("rows have to serialize to a struct");
+    };
+    Ok(names.iter().map(|name| fields.remove(*name).unwrap_or(Value::Null)).collect())
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// A date that isn't a valid YYYY-MM-DD date.
+#[derive(Debug)]
+pub struct InvalidDate(pub String);
+
+impl fmt::Display for InvalidDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid date {}", self.0)
+    }
+}
+
+impl Error for InvalidDate {}
+
+/// Days since 1970-01-01 of a YYYY-MM-DD date.
+pub fn epoch_days(date: &str) -> Result<i32, InvalidDate> {
+    let invalid = || InvalidDate(date.to_string());
+    let parts: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let [year, month, day] = parts[..] else { return Err(invalid()) };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return Err(invalid()),
+    };
+    if !(1..=month_days).contains(&day) {
+        return Err(invalid());
+    }
+    // Count years from March, so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Ok((era * 146097 + day_of_era - 719468) as i32)
+}
+
+/// Days since 1970-01-01 of a date of a parsed row, which the parser only keeps if the date is valid.
+fn date_days(date: &str) -> i32 {
+    epoch_days(date).expect("the parser rejects records with invalid dates")
+}
+
+pub struct ParquetSink {
+    writer: Option<ArrowWriter<File>>,
+    schema: SchemaRef,
+    names: Vec<&'static str>,
+    types: Vec<ColumnType>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl ParquetSink {
+    fn new(path: &str, columns: &[(&'static str, &'static str)]) -> io::Result<ParquetSink> {
+        let types: Vec<ColumnType> = columns.iter().map(|(_, definition)| ColumnType::of(definition)).collect();
+        let fields: Vec<Field> = columns
+            .iter()
+            .zip(types.iter())
+            .map(|((name, _), column_type)| {
+                let data_type = match column_type {
+                    ColumnType::Int => DataType::Int32,
+                    ColumnType::BigInt => DataType::Int64,
+                    ColumnType::Date => DataType::Date32,
+                    ColumnType::Text => DataType::Utf8,
+                };
+                Field::new(*name, data_type, true)
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let file = File::create(path).map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(io::Error::other)?;
+        Ok(ParquetSink {
+            writer: Some(writer),
+            schema,
+            names: columns.iter().map(|(name, _)| *name).collect(),
+            types,
+            rows: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, values: Vec<Value>) -> io::Result<()> {
+        self.rows.push(values);
+        if self.rows.len() >= PARQUET_BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = &self.rows;
+        let columns: Vec<ArrayRef> = self
+            .types
+            .iter()
+            .enumerate()
+            .map(|(index, column_type)| -> ArrayRef {
+                let values = rows.iter().map(|row| &row[index]);
+                match column_type {
+                    ColumnType::Int => Arc::new(values.map(|value| value.as_i64().map(|value| value as i32)).collect::<Int32Array>()),
+                    ColumnType::BigInt => Arc::new(values.map(Value::as_i64).collect::<Int64Array>()),
+                    ColumnType::Date => Arc::new(values.map(|value| value.as_str().map(date_days)).collect::<Date32Array>()),
+                    ColumnType::Text => Arc::new(values.map(text).collect::<StringArray>()),
+                }
+            })
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io::Error::other)?;
+        self.writer
+            .as_mut()
+            .expect("parquet file already finished")
+            .write(&batch)
+            .map_err(io::Error::other)?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PgBinarySink {
+    writer: BufWriter<File>,
+    names: Vec<&'static str>,
+    types: Vec<ColumnType>,
+    finished: bool,
+}
+
+impl PgBinarySink {
+    fn new(path: &str, columns: &[(&'static str, &'static str)]) -> io::Result<PgBinarySink> {
+        let file = File::create(path).map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(PGCOPY_HEADER)?;
+        Ok(PgBinarySink {
+            writer,
+            names: columns.iter().map(|(name, _)| *name).collect(),
+            types: columns.iter().map(|(_, definition)| ColumnType::of(definition)).collect(),
+            finished: false,
+        })
+    }
+
+    /// Writes a tuple: the field count, then every field as its length and its binary send representation.
+    fn write(&mut self, values: Vec<Value>) -> io::Result<()> {
+        let mut tuple = Vec::new();
+        tuple.extend_from_slice(&(values.len() as i16).to_be_bytes());
+        for (value, column_type) in values.iter().zip(self.types.iter()) {
+            if value.is_null() {
+                tuple.extend_from_slice(&(-1i32).to_be_bytes());
+                continue;
+            }
+            match column_type {
+                ColumnType::Int => {
+                    tuple.extend_from_slice(&4i32.to_be_bytes());
+                    tuple.extend_from_slice(&(value.as_i64().unwrap() as i32).to_be_bytes());
+                }
+                ColumnType::BigInt => {
+                    tuple.extend_from_slice(&8i32.to_be_bytes());
+                    tuple.extend_from_slice(&value.as_i64().unwrap().to_be_bytes());
+                }
+                ColumnType::Date => {
+                    // Postgres counts days from 2000-01-01
+                    tuple.extend_from_slice(&4i32.to_be_bytes());
+                    tuple.extend_from_slice(&(date_days(value.as_str().unwrap()) - 10957).to_be_bytes());
+                }
+                ColumnType::Text => {
+                    let text = text(value).unwrap();
+                    tuple.extend_from_slice(&(text.len() as i32).to_be_bytes());
+                    tuple.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        self.writer.write_all(&tuple)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.writer.write_all(&(-1i16).to_be_bytes())?;
+            self.finished = true;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::scratch_dir;
+    use crate::tables::{AuthorRow, Row};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn alice() -> AuthorRow {
+        AuthorRow {
+            key: 7,
+            name: String::from("Alice Smith"),
+            id: 1,
+            mdate: Some(String::from("2024-02-29")),
+            orcid: None,
+        }
+    }
+
+    fn write_row(format: OutputFormat, dir: &str) -> String {
+        let file = format!("{dir}/authors.tsv");
+        let mut sink = Sink::new(format, &file, &AuthorRow::columns()).unwrap();
+        sink.write(&alice()).unwrap();
+        sink.finish().unwrap();
+        format.file(&file)
+    }
+
+    #[test]
+    fn counts_days_since_epoch() {
+        assert_eq!(epoch_days("1970-01-01").unwrap(), 0);
+        assert_eq!(epoch_days("1969-12-31").unwrap(), -1);
+        assert_eq!(epoch_days("2000-01-01").unwrap(), 10957);
+        assert_eq!(epoch_days("2024-02-29").unwrap(), 19782);
+        assert_eq!(epoch_days("2024-03-01").unwrap(), 19783);
+        assert_eq!(epoch_days("1900-03-01").unwrap(), -25508);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in ["", "2020", "2020-01", "2020-01-01-01", "2020-13-01", "2020-00-10", "2021-02-29", "2020-04-31", "2020-01-xx"] {
+            assert!(epoch_days(date).is_err(), "{date}");
+        }
+        assert!(epoch_days("2000-02-29").is_ok());
+        assert!(epoch_days("1900-02-29").is_err());
+    }
+
+    #[test]
+    fn reports_files_it_cannot_create() {
+        let file = format!("{}/missing/authors.tsv", scratch_dir("formats_missing"));
+        for format in [OutputFormat::Tsv, OutputFormat::Csv, OutputFormat::Parquet, OutputFormat::PgBinary] {
+            let error = Sink::new(format, &file, &AuthorRow::columns()).err().unwrap();
+            assert!(error.to_string().contains("/missing/authors."), "{format:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn writes_pg_binary_tuples() {
+        let file = write_row(OutputFormat::PgBinary, &scratch_dir("formats_pg_binary"));
+        let bytes = std::fs::read(file).unwrap();
+        let (header, rest) = bytes.split_at(PGCOPY_HEADER.len());
+        assert_eq!(header, PGCOPY_HEADER);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&5i16.to_be_bytes());
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&7i32.to_be_bytes());
+        expected.extend_from_slice(&11i32.to_be_bytes());
+        expected.extend_from_slice(b"Alice Smith");
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&1i32.to_be_bytes());
+        // Postgres dates count from 2000-01-01
+        expected.extend_from_slice(&4i32.to_be_bytes());
+        expected.extend_from_slice(&(19782i32 - 10957).to_be_bytes());
+        expected.extend_from_slice(&(-1i32).to_be_bytes());
+        expected.extend_from_slice(&(-1i16).to_be_bytes());
+        assert_eq!(rest, expected.as_slice());
+    }
+
+    #[test]
+    fn writes_parquet_rows() {
+        let file = write_row(OutputFormat::Parquet, &scratch_dir("formats_parquet"));
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(File::open(file).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 1);
+        let column = |name: &str| batch.column_by_name(name).unwrap();
+        assert_eq!(column("key").as_any().downcast_ref::<Int32Array>().unwrap().value(0), 7);
+        assert_eq!(column("name").as_any().downcast_ref::<StringArray>().unwrap().value(0), "Alice Smith");
+        assert_eq!(column("id").as_any().downcast_ref::<Int32Array>().unwrap().value(0), 1);
+        assert_eq!(column("mdate").as_any().downcast_ref::<Date32Array>().unwrap().value(0), 19782);
+        assert!(column("orcid").is_null(0));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::tables::{dblp_tables, file_in, Changes, TableInfo};
+use csv::{Reader, ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Kind of a change set of a table.
+#[derive(Clone, Copy, Debug)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Insert => "insert",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+/// Path of a change set of the table in `dir`. Inserts and updates hold whole rows, deletes only the key column.
+pub fn change_file(dir: &str, table: &TableInfo, kind: ChangeKind) -> String {
+    format!("{dir}/{}.{}.tsv", table.name.to_lowercase(), kind.name())
+}
+
+/// Number of rows in the change sets of a table.
+#[derive(Debug, Default)]
+pub struct TableChanges {
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// Diffs the tsv files of a new parse in `dir` against the ones of a previous parse in `previous_dir`
+/// and writes the insert, update and delete change sets of every table to `changes_dir`.
+///
+/// Both parses have to share their ids, see `Parser::seed_from`. A record counts as updated when
+/// its row differs, which DBLP signals by bumping the mdate. Comparing the whole row also catches
+/// changes that come from other records, such as an author that was resolved to a new person.
+/// Owned rows that still reference a deleted record, such as a citation of a removed publication,
+/// are deleted and left out of the inserts, so the record can be deleted without breaking a foreign key.
+pub fn diff_dblp(dir: &str, previous_dir: &str, changes_dir: &str) -> Result<Vec<(&'static str, TableChanges)>, Box<dyn Error>> {
+    fs::create_dir_all(changes_dir)?;
+    let diff = Diff { dir, previous_dir, changes_dir };
+    // Keys of the inserted, updated and deleted records per table, whose owned rows get replaced
+    let mut changed: HashMap<&'static str, HashSet<String>> = HashMap::new();
+    // Keys of the deleted records per table
+    let mut deleted: HashMap<&'static str, HashSet<String>> = HashMap::new();
+    let mut summary = Vec::new();
+    for table in dblp_tables() {
+        let changes = match table.changes {
+            Changes::Record { key, .. } => diff_records(
+                &table,
+                &diff,
+                key,
+                changed.entry(table.name).or_default(),
+                deleted.entry(table.name).or_default(),
+            )?,
+            Changes::OwnedBy { column, parent } => {
+                let dangling: Vec<(usize, &HashSet<String>)> = table
+                    .foreign_keys
+                    .iter()
+                    .filter_map(|foreign_key| {
+                        deleted.get(foreign_key.table).map(|keys| (column_index(&table, foreign_key.column), keys))
+                    })
+                    .collect();
+                diff_owned(&table, &diff, column, changed.entry(parent).or_default(), &dangling)?
+            }
+            Changes::Lookup(key) => diff_lookup(&table, &diff, key)?,
+        };
+        summary.push((table.name, changes));
+    }
+    Ok(summary)
+}
+
+/// Directories of the two parses and of the change sets.
+struct Diff<'a> {
+    dir: &'a str,
+    previous_dir: &'a str,
+    changes_dir: &'a str,
+}
+
+impl Diff<'_> {
+    fn current(&self, table: &TableInfo) -> String {
+        file_in(self.dir, table.file)
+    }
+
+    fn previous(&self, table: &TableInfo) -> String {
+        file_in(self.previous_dir, table.file)
+    }
+
+    fn create(&self, table: &TableInfo, kind: ChangeKind, columns: &[&str]) -> Result<Writer<File>, Box<dyn Error>> {
+        let mut writer = WriterBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(false)
+            .from_path(change_file(self.changes_dir, table, kind))?;
+        writer.write_record(columns)?;
+        Ok(writer)
+    }
+}
+
+fn diff_records(
+    table: &TableInfo,
+    diff: &Diff,
+    key: &str,
+    changed: &mut HashSet<String>,
+    deleted: &mut HashSet<String>,
+) -> Result<TableChanges, Box<dyn Error>> {
+    let key_index = column_index(table, key);
+    let mut previous: HashMap<String, u64> = HashMap::new();
+    for record in open(table, &diff.previous(table))?.records() {
+        let record = record?;
+        previous.insert(record[key_index].to_string(), hash_record(&record, None));
+    }
+
+    let mut changes = TableChanges::default();
+    let mut inserts = diff.create(table, ChangeKind::Insert, &table.columns)?;
+    let mut updates = diff.create(table, ChangeKind::Update, &table.columns)?;
+    let mut deletes = diff.create(table, ChangeKind::Delete, &[key])?;
+    for record in open(table, &diff.current(table))?.records() {
+        let record = record?;
+        match previous.remove(&record[key_index]) {
+            None => {
+                inserts.write_record(&record)?;
+                changes.inserted += 1;
+            }
+            Some(hash) if hash != hash_record(&record, None) => {
+                updates.write_record(&record)?;
+                changes.updated += 1;
+            }
+            Some(_) => continue,
+        }
+        changed.insert(record[key_index].to_string());
+    }
+    // Whatever is left of the previous parse is gone from the dump
+    for key in previous.into_keys() {
+        deletes.write_record([&key])?;
+        changes.deleted += 1;
+        changed.insert(key.clone());
+        deleted.insert(key);
+    }
+    inserts.flush()?;
+    updates.flush()?;
+    deletes.flush()?;
+    Ok(changes)
+}
+
+/// Owned rows have no identity of their own, so the rows of an owner are replaced as a whole when the
+/// owner changed or the rows differ in anything but their generated id. Rows of the new parse whose
+/// foreign key is one of the `dangling` deleted keys are left out, which replaces the rows of their owner too.
+fn diff_owned(
+    table: &TableInfo,
+    diff: &Diff,
+    column: &str,
+    changed: &HashSet<String>,
+    dangling: &[(usize, &HashSet<String>)],
+) -> Result<TableChanges, Box<dyn Error>> {
+    let owner_index = column_index(table, column);
+    let id_index = table.columns.iter().position(|name| *name == "id");
+    let is_dangling = |record: &StringRecord| dangling.iter().any(|(index, keys)| keys.contains(&record[*index]));
+    let previous = group_owned(table, &diff.previous(table), owner_index, id_index, |_| false)?;
+    let current = group_owned(table, &diff.current(table), owner_index, id_index, is_dangling)?;
+    let differing: HashSet<String> = previous
+        .keys()
+        .chain(current.keys())
+        .filter(|owner| !changed.contains(*owner) && previous.get(*owner) != current.get(*owner))
+        .cloned()
+        .collect();
+    let replaced = |owner: &str| changed.contains(owner) || differing.contains(owner);
+
+    let mut changes = TableChanges::default();
+    let mut deletes = diff.create(table, ChangeKind::Delete, &[column])?;
+    for (owner, (_, rows)) in previous.iter() {
+        if replaced(owner) {
+            deletes.write_record([owner])?;
+            changes.deleted += rows;
+        }
+    }
+    let mut inserts = diff.create(table, ChangeKind::Insert, &table.columns)?;
+    for record in open(table, &diff.current(table))?.records() {
+        let record = record?;
+        if replaced(&record[owner_index]) && !is_dangling(&record) {
+            inserts.write_record(&record)?;
+            changes.inserted += 1;
+        }
+    }
+    deletes.flush()?;
+    inserts.flush()?;
+    Ok(changes)
+}
+
+fn diff_lookup(table: &TableInfo, diff: &Diff, key: &str) -> Result<TableChanges, Box<dyn Error>> {
+    let key_index = column_index(table, key);
+    let mut previous: HashSet<String> = HashSet::new();
+    for record in open(table, &diff.previous(table))?.records() {
+        previous.insert(record?[key_index].to_string());
+    }
+
+    let mut changes = TableChanges::default();
+    let mut inserts = diff.create(table, ChangeKind::Insert, &table.columns)?;
+    for record in open(table, &diff.current(table))?.records() {
+        let record = record?;
+        if !previous.contains(&record[key_index]) {
+            inserts.write_record(&record)?;
+            changes.inserted += 1;
+        }
+    }
+    inserts.flush()?;
+    Ok(changes)
+}
+
+/// Order independent hash and number of the rows of every owner, ignoring the generated id and the skipped rows.
+fn group_owned(
+    table: &TableInfo,
+    file: &str,
+    owner_index: usize,
+    id_index: Option<usize>,
+    skip: impl Fn(&StringRecord) -> bool,
+) -> Result<HashMap<String, (u64, usize)>, Box<dyn Error>> {
+    let mut groups: HashMap<String, (u64, usize)> = HashMap::new();
+    for record in open(table, file)?.records() {
+        let record = record?;
+        if skip(&record) {
+            continue;
+        }
+        let group = groups.entry(record[owner_index].to_string()).or_default();
+        group.0 = group.0.wrapping_add(hash_record(&record, id_index));
+        group.1 += 1;
+    }
+    Ok(groups)
+}
+
+fn hash_record(record: &StringRecord, skip: Option<usize>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (index, field) in record.iter().enumerate() {
+        if Some(index) != skip {
+            field.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn column_index(table: &TableInfo, column: &str) -> usize {
+    table.columns.iter().position(|name| *name == column).unwrap()
+}
+
+/// Opens a table file, making sure both parses wrote the same columns.
+fn open(table: &TableInfo, file: &str) -> Result<Reader<File>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new().delimiter(b'\t').from_path(file)?;
+    if reader.headers()? != table.columns.as_slice() {
+        return Err(format!("columns of {file} don't match the {} table", table.name).into());
+    }
+    Ok(reader)
+}
+
+
+/// Creates an empty staging table with the given columns of a table, to copy a change set into.
+pub fn staging_sql(table: &TableInfo, staging: &str, columns: &[&str]) -> String {
+    format!("CREATE TEMP TABLE {staging} AS SELECT {} FROM {} LIMIT 0;", columns.join(", "), table.name)
+}
+
+/// Deletes the rows whose key is in the staging table.
+pub fn delete_sql(table: &TableInfo, staging: &str, key: &str) -> String {
+    format!(
+        "DELETE FROM {table} USING {staging} WHERE {table}.{key} = {staging}.{key};",
+        table = table.name
+    )
+}
+
+/// Overwrites the changeable columns of the rows whose key is in the staging table.
+pub fn update_sql(table: &TableInfo, staging: &str, key: &str) -> String {
+    // Leaving out the identity also keeps DuckDB from turning the update of its indexed
+    // columns into a delete and insert, which fails for rows referenced by foreign keys
+    let identity = match table.changes {
+        Changes::Record { identity, .. } => identity,
+        _ => &[],
+    };
+    let assignments: Vec<String> = table
+        .columns
+        .iter()
+        .filter(|column| **column != key && !identity.contains(column))
+        .map(|column| format!("{column} = {staging}.{column}"))
+        .collect();
+    format!(
+        "UPDATE {table} SET {} FROM {staging} WHERE {table}.{key} = {staging}.{key};",
+        assignments.join(", "),
+        table = table.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{parse_fixture, scratch_dir};
+    use crate::formats::OutputFormat;
+    use crate::tables::{PublicationRow, ReferenceRow, Row};
+
+    /// Rows of a change set, without the header.
+    fn change_set(dir: &str, table: &str, kind: ChangeKind) -> Vec<Vec<String>> {
+        let table = dblp_tables().into_iter().find(|info| info.name == table).unwrap();
+        ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(change_file(dir, &table, kind))
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn diffs_two_parses() {
+        let (previous, current, changes) = (scratch_dir("diff_previous"), scratch_dir("diff_current"), scratch_dir("diff_changes"));
+        parse_fixture("dblp.xml", &previous, OutputFormat::Tsv, None);
+        parse_fixture("dblp.next.xml", &current, OutputFormat::Tsv, Some(&previous));
+        let summary = diff_dblp(&current, &previous, &changes).unwrap();
+        let counts = |table: &str| {
+            let (_, changes) = summary.iter().find(|(name, _)| *name == table).unwrap();
+            (changes.inserted, changes.updated, changes.deleted)
+        };
+
+        assert_eq!(counts(PublicationRow::TABLE), (1, 1, 1));
+        assert_eq!(change_set(&changes, PublicationRow::TABLE, ChangeKind::Delete), vec![vec!["conf/y/Old18"]]);
+        assert_eq!(change_set(&changes, PublicationRow::TABLE, ChangeKind::Update)[0][0], "journals/x/Smith20");
+        assert_eq!(change_set(&changes, "Venues", ChangeKind::Insert).len(), 2);
+        // The unchanged Jones19 still cites the deleted Old18, so its citations are replaced by none
+        let mut deleted = change_set(&changes, ReferenceRow::TABLE, ChangeKind::Delete);
+        deleted.sort();
+        assert_eq!(deleted, vec![vec!["conf/y/Jones19"], vec!["journals/x/Smith20"]]);
+        assert_eq!(
+            change_set(&changes, ReferenceRow::TABLE, ChangeKind::Insert),
+            vec![vec!["cite", "journals/x/Smith20", "conf/y/Jones19"]]
+        );
+        // Alice got a new affiliation, so all of hers are replaced
+        assert_eq!(counts("Affiliations"), (2, 0, 1));
+    }
+}
@@ -1,8 +1,14 @@
 #[cfg(feature = "duckdb")]
 use crate::duckdb_connector::DuckDBConnection;
-use crate::parser::Parser;
-use crate::postgres_connector::PostgresConnection;
+use crate::formats::OutputFormat;
+use crate::osm::OsmLoader;
+use crate::parser::{ParseMode, Parser};
+use crate::plans::QueryPlan;
+use crate::postgres_connector::{Osm2pgsqlOptions, PostgresConnection};
 use crate::qlever_connector::QLeverConnection;
+use crate::rdf::RdfFormat;
+use crate::schemas::SchemaVariant;
+use crate::validation::IntegrityPolicy;
 use async_compression::tokio::bufread::GzipDecoder;
 use clap::{Arg, ArgAction, command, value_parser};
 use csv::ReaderBuilder;
@@ -11,9 +17,9 @@ use serde::Deserialize;
 use std::time::Duration;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::{File, create_dir_all};
+use std::fs::{File, create_dir_all, rename};
 use std::io::Write;
-use std::ops::AddAssign;
+use std::path::Path;
 use std::process::Command;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::runtime::Runtime;
@@ -21,10 +27,25 @@ use tokio_util::io::StreamReader;
 
 #[cfg(feature = "duckdb")]
 mod duckdb_connector;
+mod containers;
+mod dialect;
+#[cfg(test)]
+mod fixtures;
+mod formats;
+mod incremental;
+mod osm;
 mod parser;
+mod plans;
 mod postgres_connector;
 mod qlever_connector;
-
+mod rdf;
+mod schemas;
+mod tables;
+mod validation;
+
+const DATA_DIR: &str = "/data";
+const PREVIOUS_DIR: &str = "/data/previous";
+const CHANGES_DIR: &str = "/data/changes";
 const VENUE_FILE: &str = "/data/venues.tsv";
 const PUBLISHER_FILE: &str = "/data/publishers.tsv";
 const EDITOR_FILE: &str = "/data/editors.tsv";
@@ -37,14 +58,45 @@ const PUBLICATION_AUTHORS_FILE: &str = "/data/publication_authors.tsv";
 const AUTHOR_WEBSITES_FILE: &str = "/data/authors_website.tsv";
 const AFFILIATIONS_FILE: &str = "/data/affiliations.tsv";
 const ALIAS_FILE: &str = "/data/aliases.tsv";
+const AWARDS_FILE: &str = "/data/awards.tsv";
+const UNAMES_FILE: &str = "/data/unames.tsv";
+const AUTHOR_NOTES_FILE: &str = "/data/author_notes.tsv";
+const AUTHOR_IDENTIFIERS_FILE: &str = "/data/author_identifiers.tsv";
+const SCHEMA_FILE: &str = "/data/create_tables_dblp.sql";
+const REJECTS_FILE: &str = "/data/rejects.tsv";
 
 fn main() {
     // CLI Setup
     let matches = command!()
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            clap::Command::new("cleanup")
+                .about("remove the containers of earlier runs, such as ones left behind by a crash"),
+        )
+        .subcommand(
+            clap::Command::new("parse")
+                .about("parse a DBLP xml file into tables without running any tests")
+                .arg(
+                    Arg::new("input")
+                        .value_parser(value_parser!(String))
+                        .help("path to the DBLP xml file, may be gzipped")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(String))
+                        .default_value(DATA_DIR)
+                        .help("directory the tables are written to")
+                        .required(false),
+                ),
+        )
         .arg(
             Arg::new("query_file")
                 .value_parser(value_parser!(String))
-                .help("path to a query file with the tsv format: (name sql sparql columns rows)")
+                .help("path to a query file with the tsv format: (name [schemas] postgresql duckdb sparql), an empty duckdb query is translated from the postgresql one, the schema variant needs at least one query")
                 .required(true),
         )
         .arg(
@@ -78,6 +130,13 @@ fn main() {
                 .help("save raw results to tsv file")
                 .required(false),
         )
+        .arg(
+            Arg::new("plans")
+                .long("plans")
+                .action(ArgAction::SetTrue)
+                .help("capture the plan of each query once before the timed iterations and save them with a summary")
+                .required(false),
+        )
         .arg(
             Arg::new("qlever")
                 .short('q')
@@ -99,8 +158,215 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .required(false),
         )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("fail on the first malformed DBLP record instead of logging it to the reject file")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("synthesize_authors")
+                .long("synthesize-authors")
+                .action(ArgAction::SetTrue)
+                .help("create authors for names on DBLP publications that have no person record instead of dropping them")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("validate")
+                .long("validate")
+                .value_parser(IntegrityPolicy::NAMES)
+                .help("check the keys and foreign keys of the parsed DBLP tables and drop, quarantine or keep violating rows")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(OutputFormat::NAMES)
+                .default_value("tsv")
+                .help("file format the DBLP parser writes and the databases load")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("rdf")
+                .long("rdf")
+                .value_parser(RdfFormat::NAMES)
+                .help("also write the parsed DBLP data as RDF, which QLever then indexes instead of the upstream dump")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("setup")
+                .long("setup")
+                .value_parser(value_parser!(String))
+                .help("path to a setup file with the tsv format: (dataset name [schemas] [extensions] postgresql duckdb), run after loading the data, a failing statement fails the run; extensions lists the Postgres extensions a statement needs")
+                .required(false),
+        )
+        .arg(
+            Arg::new("schema")
+                .long("schema")
+                .value_parser(SchemaVariant::NAMES)
+                .default_value("normalized")
+                .help("relational layout the DBLP data is loaded into")
+                .required(false),
+        )
+        .arg(
+            Arg::new("postgres_url")
+                .long("postgres-url")
+                .value_parser(value_parser!(String))
+                .conflicts_with_all(["postgres_image", "postgres_setting"])
+                .help("connection string of an existing Postgres server to test instead of starting a container, passwords are read from PGPASSFILE or ~/.pgpass, sslmode=require encrypts the connection without verifying the certificate like psql")
+                .required(false),
+        )
+        .arg(
+            Arg::new("postgres_image")
+                .long("postgres-image")
+                .value_parser(value_parser!(String))
+                .help("docker image of the Postgres server, postgis/postgis:18-3.6 for osm datasets and postgres:latest otherwise")
+                .required(false),
+        )
+        .arg(
+            Arg::new("postgres_setting")
+                .long("postgres-setting")
+                .value_parser(parse_setting)
+                .action(ArgAction::Append)
+                .help("server setting passed to Postgres as name=value, such as shared_buffers=4GB, can be repeated")
+                .required(false),
+        )
+        .arg(
+            Arg::new("load_jobs")
+                .long("load-jobs")
+                .value_parser(value_parser!(u16).range(1..))
+                .default_value("1")
+                .help("number of connections Postgres loads the DBLP tables on in parallel, more than one defers the foreign keys")
+                .required(false),
+        )
+        .arg(
+            Arg::new("defer_constraints")
+                .long("defer-constraints")
+                .action(ArgAction::SetTrue)
+                .help("create the Postgres tables without keys and foreign keys and add them after loading the DBLP data")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm_loader")
+                .long("osm-loader")
+                .value_parser(OsmLoader::NAMES)
+                .default_value("osm2pgsql")
+                .help("how Postgres loads OSM data: osm2pgsql into its planet_osm tables, or raw into the osm table DuckDB loads too")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm2pgsql_style")
+                .long("osm2pgsql-style")
+                .value_parser(value_parser!(String))
+                .conflicts_with("osm2pgsql_hstore")
+                .help("Lua style file osm2pgsql imports OSM data into Postgres with, using its flex output")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm2pgsql_slim")
+                .long("osm2pgsql-slim")
+                .action(ArgAction::SetTrue)
+                .help("run osm2pgsql in slim mode, keeping its intermediate tables in the database")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm2pgsql_cache")
+                .long("osm2pgsql-cache")
+                .value_parser(value_parser!(u32))
+                .help("node cache size of osm2pgsql in MB")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm2pgsql_processes")
+                .long("osm2pgsql-processes")
+                .value_parser(value_parser!(u16).range(1..))
+                .help("number of threads osm2pgsql uses")
+                .required(false),
+        )
+        .arg(
+            Arg::new("osm2pgsql_hstore")
+                .long("osm2pgsql-hstore")
+                .action(ArgAction::SetTrue)
+                .help("let osm2pgsql put all tags without a column of their own into an hstore tags column")
+                .required(false),
+        )
+        .arg(
+            Arg::new("keep_containers")
+                .long("keep-containers")
+                .action(ArgAction::SetTrue)
+                .help("leave the Postgres and QLever containers and their data running after the run for debugging, remove them with the cleanup subcommand")
+                .required(false),
+        )
+        .arg(
+            Arg::new("label")
+                .long("label")
+                .value_parser(value_parser!(String))
+                .help("added to the names of the result files, so the results of differently configured runs can be kept side by side")
+                .required(false),
+        )
+        .arg(
+            Arg::new("incremental")
+                .long("incremental")
+                .action(ArgAction::SetTrue)
+                .help("diff the new DBLP dump against the previous parse and load the previous parse plus the changes")
+                .required(false),
+        )
         .get_matches();
 
+    let options = ParseOptions {
+        mode: if matches.get_flag("strict") {
+            ParseMode::Strict
+        } else {
+            ParseMode::Lenient
+        },
+        format: OutputFormat::from_name(matches.get_one::<String>("format").unwrap()).unwrap(),
+        rdf: matches.get_one::<String>("rdf").and_then(|name| RdfFormat::from_name(name)),
+        synthesize_authors: matches.get_flag("synthesize_authors"),
+        validate: matches.get_one::<String>("validate").and_then(|name| IntegrityPolicy::from_name(name)),
+    };
+    if options.validate.is_some() && options.format.delimiter().is_none() {
+        eprintln!("The validation reads tsv or csv files and can't be used with --format {}", options.format.name());
+        std::process::exit(1);
+    }
+
+    if let Some(("cleanup", _)) = matches.subcommand() {
+        match containers::remove_stale() {
+            Ok(removed) => {
+                for name in removed.iter() {
+                    println!("Removed {name}");
+                }
+                println!("Removed {} containers", removed.len());
+            }
+            Err(e) => {
+                eprintln!("Failed removing containers: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(("parse", parse)) = matches.subcommand() {
+        let input = parse.get_one::<String>("input").unwrap();
+        let output = parse.get_one::<String>("output").unwrap();
+        let parsed = create_dir_all(output)
+            .map_err(|e| e.into())
+            .and_then(|_| parse_dblp(input, output, &options, None));
+        match parsed {
+            Ok(()) => println!("Parsed {input} into {output}"),
+            Err(e) => {
+                eprintln!("Failed parsing DBLP data: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let queries = matches
         .get_one::<String>("query_file")
         .expect("No 'query_file' argument");
@@ -108,6 +374,43 @@ fn main() {
         .get_one::<String>("data_set")
         .expect("data_set is required");
     let iter = matches.get_one::<usize>("iter").unwrap().to_owned();
+    let schema = SchemaVariant::from_name(matches.get_one::<String>("schema").unwrap()).unwrap();
+    let config = RunConfig {
+        incremental: matches.get_flag("incremental"),
+        format: options.format,
+        rdf: options.rdf,
+        schema,
+        setup: match matches.get_one::<String>("setup") {
+            Some(file) => read_setup_file(file, data_set, schema)
+                .unwrap_or_else(|e| panic!("Failed to read setup file {file}: {e}")),
+            None => Vec::new(),
+        },
+        load_jobs: *matches.get_one::<u16>("load_jobs").unwrap() as usize,
+        defer_constraints: matches.get_flag("defer_constraints"),
+        osm_loader: OsmLoader::from_name(matches.get_one::<String>("osm_loader").unwrap()).unwrap(),
+        osm2pgsql: Osm2pgsqlOptions {
+            style: matches.get_one::<String>("osm2pgsql_style").cloned(),
+            slim: matches.get_flag("osm2pgsql_slim"),
+            cache: matches.get_one::<u32>("osm2pgsql_cache").copied(),
+            processes: matches.get_one::<u16>("osm2pgsql_processes").copied(),
+            hstore: matches.get_flag("osm2pgsql_hstore"),
+        },
+        keep_containers: matches.get_flag("keep_containers"),
+        postgres_url: matches.get_one::<String>("postgres_url").cloned(),
+        postgres_image: matches.get_one::<String>("postgres_image").cloned(),
+        postgres_settings: matches
+            .get_many::<(String, String)>("postgres_setting")
+            .map(|settings| settings.cloned().collect())
+            .unwrap_or_default(),
+    };
+    let results_name = |test: &Database| match matches.get_one::<String>("label") {
+        Some(label) => format!("/extern/results/{}.{label}", test.name()),
+        None => format!("/extern/results/{}", test.name()),
+    };
+    if config.incremental && config.format != OutputFormat::Tsv {
+        eprintln!("The incremental mode diffs tsv files and can't be used with --format {}", config.format.name());
+        std::process::exit(1);
+    }
 
     let mut tests: Vec<Database> = Vec::new();
     if matches.get_flag("qlever") {
@@ -122,19 +425,37 @@ fn main() {
     }
     
     // TODO add more datasets
-    match data_set.split(" ").collect::<Vec<&str>>()[0].as_ref() {
+    match data_set.split(" ").collect::<Vec<&str>>()[0] {
         "dblp"
-            if tests
-                .iter()
-                .any(|x| x.name() == "duckdb" || x.name() == "postgres") =>
+            if tests.iter().any(|x| {
+                x.name() == "duckdb" || x.name() == "postgres" || (x.name() == "qlever" && config.rdf.is_some())
+            }) =>
         {
             let rt = Runtime::new().unwrap();
             let handle = rt.handle();
 
             let _ = handle.block_on(download_dblp_data("/data/dblp.xml".into()));
-            let mut parser = Parser::new("/data/dblp.xml");
-            parser.run();
-            println!("Finished Parsing DBLP data");
+            if config.incremental
+                && let Err(e) = keep_previous_parse()
+            {
+                eprintln!("Failed to keep the previous DBLP parse: {e}");
+                std::process::exit(1);
+            }
+            let previous = config.incremental.then_some(PREVIOUS_DIR);
+            match parse_dblp("/data/dblp.xml", DATA_DIR, &options, previous) {
+                Ok(()) => println!("Finished Parsing DBLP data"),
+                Err(e) => {
+                    eprintln!("Failed parsing DBLP data: {e}");
+                    std::process::exit(1);
+                }
+            }
+            if config.incremental {
+                let changes = incremental::diff_dblp(DATA_DIR, PREVIOUS_DIR, CHANGES_DIR).expect("Failed to diff DBLP data");
+                for (table, changes) in changes {
+                    println!("{table}: {changes:?}");
+                }
+                println!("Finished diffing DBLP data");
+            }
         }
         "osm-country"
             if tests
@@ -162,38 +483,38 @@ fn main() {
         println!("Start of Test: {}", test.name());
         // Create Connection and insert Data
         let mut conn = test
-            .to_connection(&data_set.to_string())
-            .expect(format!("Failed to create connection for {}", test.name()).as_str());
+            .to_connection(data_set, &config)
+            .unwrap_or_else(|e| panic!("Failed to create connection for {}: {e}", test.name()));
+        // Capture Plans
+        if matches.get_flag("plans") {
+            let _ = create_dir_all("/extern/results");
+            capture_plans(queries, config.schema, &mut conn, &results_name(&test))
+                .unwrap_or_else(|e| panic!("Failed while capturing plans of {}: {e}", test.name()));
+        }
         // Run Queries
-        let results = run_test(queries, iter, &mut conn)
-            .expect(format!("Failed while testing for {}", test.name()).as_str());
+        let results = run_test(queries, config.schema, iter, &mut conn)
+            .unwrap_or_else(|e| panic!("Failed while testing for {}: {e}", test.name()));
         // Save Results
         let _ = create_dir_all("/extern/results");
+        let settings = conn.settings(&config);
+        if !settings.is_empty() {
+            write_settings(&settings, format!("{}.settings.tsv", results_name(&test)))
+                .unwrap_or_else(|e| panic!("Failed while writing settings of {} to file: {e}", test.name()));
+        }
         if matches.get_flag("raw") {
-            write_results(&results, format!("/extern/results/{}.raw.tsv", test.name())).expect(
-                format!(
-                    "Failed while writing raw results of {} to file",
-                    test.name()
-                )
-                .as_str(),
-            );
+            write_results(&results, format!("{}.raw.tsv", results_name(&test)))
+                .unwrap_or_else(|e| panic!("Failed while writing raw results of {} to file: {e}", test.name()));
         }
         if matches.get_flag("aggregate") {
             write_results_aggregated(
                 &results,
-                format!("/extern/results/{}.aggregate.tsv", test.name()),
+                format!("{}.aggregate.tsv", results_name(&test)),
             )
-            .expect(
-                format!(
-                    "Failed while writing aggregate results of {} to file",
-                    test.name()
-                )
-                .as_str(),
-            );
+            .unwrap_or_else(|e| panic!("Failed while writing aggregate results of {} to file: {e}", test.name()));
         }
         // Clean Up
         conn.close()
-            .expect(format!("Failed to close connection for {}", test.name()).as_str());
+            .unwrap_or_else(|e| panic!("Failed to close connection for {}: {e}", test.name()));
         clear_cache().expect("Failed to clear cache");
         println!("End of Test: {}", test.name());
     }
@@ -217,38 +538,178 @@ impl Database {
         }
     }
 
-    pub fn to_connection(&self, dataset: &String) -> Result<Connection, Box<dyn Error>> {
+    pub fn to_connection(&self, dataset: &str, config: &RunConfig) -> Result<Connection, Box<dyn Error>> {
         match self {
-            Database::QLever => Ok(Connection::QLever(QLeverConnection::new(dataset)?)),
+            Database::QLever => Ok(Connection::QLever(QLeverConnection::new(dataset, config)?)),
             #[cfg(feature = "duckdb")]
-            Database::DuckDB => Ok(Connection::DuckDB(DuckDBConnection::new(dataset)?)),
-            Database::Postgres => Ok(Connection::PostGres(PostgresConnection::new(dataset)?)),
+            Database::DuckDB => Ok(Connection::DuckDB(DuckDBConnection::new(dataset, config)?)),
+            Database::Postgres => Ok(Connection::PostGres(PostgresConnection::new(dataset, config)?)),
         }
     }
 }
 
+/// Options of a test run that change how the connectors load their data.
+pub struct RunConfig {
+    /// Load the previous DBLP parse and apply the changes of the new one on top of it.
+    pub incremental: bool,
+    /// Format of the parsed DBLP tables.
+    pub format: OutputFormat,
+    /// Write the parsed DBLP data as RDF and let QLever index it.
+    pub rdf: Option<RdfFormat>,
+    /// Relational layout of the DBLP data in the SQL engines.
+    pub schema: SchemaVariant,
+    /// Statements of the dataset that run after the data is loaded, such as secondary indexes.
+    pub setup: Vec<SetupRecord>,
+    /// Number of connections Postgres loads the DBLP tables on.
+    pub load_jobs: usize,
+    /// Add the keys and foreign keys of the Postgres tables after loading the DBLP data.
+    pub defer_constraints: bool,
+    /// How Postgres loads OSM data.
+    pub osm_loader: OsmLoader,
+    /// How osm2pgsql imports OSM data into Postgres.
+    pub osm2pgsql: Osm2pgsqlOptions,
+    /// Leave the containers of the engines running after the run.
+    pub keep_containers: bool,
+    /// Connection string of an existing Postgres server, None to start a container.
+    pub postgres_url: Option<String>,
+    /// Docker image of the Postgres server, None for the default of the dataset.
+    pub postgres_image: Option<String>,
+    /// Server settings passed to Postgres on startup, as (name, value).
+    pub postgres_settings: Vec<(String, String)>,
+}
+
+/// Splits a `name=value` server setting.
+fn parse_setting(setting: &str) -> Result<(String, String), String> {
+    match setting.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected name=value, got {setting}")),
+    }
+}
+
+/// How the DBLP dump is parsed into tables, shared by the parse subcommand and test runs.
+struct ParseOptions {
+    mode: ParseMode,
+    format: OutputFormat,
+    rdf: Option<RdfFormat>,
+    synthesize_authors: bool,
+    validate: Option<IntegrityPolicy>,
+}
+
+/// Parses a DBLP dump into the tables in `dir`, continuing the ids of the parse in `previous` if given,
+/// and validates the tables if asked to. Prints what the parse produced.
+fn parse_dblp(input: &str, dir: &str, options: &ParseOptions, previous: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut parser = Parser::new(input, dir, options.mode, options.format)?;
+    if let Some(previous) = previous {
+        parser.seed_from(previous)?;
+    }
+    if let Some(rdf) = options.rdf {
+        parser.write_rdf(rdf)?;
+    }
+    if options.synthesize_authors {
+        parser.synthesize_authors();
+    }
+    println!("{}", parser.run()?);
+    if let Some(policy) = options.validate {
+        for (table, violations) in validation::validate_dblp(dir, options.format, policy)? {
+            if !violations.is_empty() {
+                println!("{table}: {violations:?}");
+            }
+        }
+        println!("Finished validating DBLP data");
+    }
+    Ok(())
+}
+
+/// Moves the tsv files of the last DBLP parse out of the way, so the new parse can be diffed against them.
+/// Nothing is moved unless every table file is there, so a partial parse never ends up half moved.
+fn keep_previous_parse() -> Result<(), Box<dyn Error>> {
+    let missing: Vec<&str> = tables::dblp_tables()
+        .iter()
+        .map(|table| table.file)
+        .filter(|file| !Path::new(file).exists())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("no previous DBLP parse to diff against, missing {}", missing.join(", ")).into());
+    }
+    create_dir_all(PREVIOUS_DIR)?;
+    for table in tables::dblp_tables() {
+        rename(table.file, tables::file_in(PREVIOUS_DIR, table.file)).map_err(|e| format!("{}: {e}", table.file))?;
+    }
+    Ok(())
+}
+
 pub enum QueryLang {
     SQL,
     SPARQL,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct TSVRecord {
     name: String,
+    /// Comma separated schema variants the query applies to, empty or missing for all of them.
+    #[serde(default)]
+    schemas: String,
     postgresql: String,
     duckdb: String,
     sparql: String,
 }
 
-fn read_test_file(filename: &str) -> Result<Vec<TSVRecord>, Box<dyn Error>> {
+/// A statement of the post-load phase, in the dialect of each SQL engine. Empty cells are skipped.
+#[derive(Debug, Deserialize)]
+pub struct SetupRecord {
+    pub dataset: String,
+    pub name: String,
+    /// Comma separated schema variants the statement applies to, empty or missing for all of them.
+    #[serde(default)]
+    pub schemas: String,
+    /// Comma separated Postgres extensions the statement needs, installed before the data is loaded.
+    #[serde(default)]
+    pub extensions: String,
+    pub postgresql: String,
+    pub duckdb: String,
+}
+
+/// Reads the setup statements of the dataset, matched by its first word, that apply to the schema variant.
+fn read_setup_file(filename: &str, dataset: &str, schema: SchemaVariant) -> Result<Vec<SetupRecord>, Box<dyn Error>> {
+    let dataset = dataset.split(" ").next().unwrap_or_default();
+    let records = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_path(filename)?
+        .deserialize()
+        .collect::<Result<Vec<SetupRecord>, _>>()?;
+    let mut setup = Vec::new();
+    for record in records.into_iter().filter(|record| record.dataset == dataset) {
+        if schema.listed_in(&record.schemas).map_err(|e| format!("setup {} {e}", record.name))? {
+            setup.push(record);
+        }
+    }
+    Ok(setup)
+}
+
+/// Reads the queries that apply to the schema variant. Fails if there are none, as the tables of some variants
+/// can't be queried with the queries of another.
+fn read_test_file(filename: &str, schema: SchemaVariant) -> Result<Vec<TSVRecord>, Box<dyn Error>> {
     let mut reader = ReaderBuilder::new()
         .delimiter(b'\t')
         .has_headers(true)
         .from_path(filename)
-        .expect("Unable to open file");
-    let results: Vec<TSVRecord> = reader
-        .deserialize()
-        .collect::<Result<Vec<TSVRecord>, _>>()?;
+        .map_err(|e| format!("Unable to open {filename}: {e}"))?;
+    let mut results = Vec::new();
+    for record in reader.deserialize() {
+        let record: TSVRecord = record?;
+        if schema.listed_in(&record.schemas).map_err(|e| format!("query {} {e}", record.name))? {
+            results.push(record);
+        }
+    }
+    if results.is_empty() {
+        return Err(format!("{filename} has no queries for the {} schema", schema.name()).into());
+    }
+    // The duckdb column only overrides queries that can't be translated
+    for record in results.iter_mut().filter(|record| record.duckdb.is_empty()) {
+        record.duckdb = dialect::postgres_to_duckdb(&record.postgresql);
+    }
     Ok(results)
 }
 
@@ -260,29 +721,46 @@ pub enum Connection {
 }
 
 impl Connection {
-    pub fn run_test_query(&mut self, record: &TSVRecord) -> u128 {
-        match self {
+    pub fn run_test_query(&mut self, record: &TSVRecord) -> Result<u128, Box<dyn Error>> {
+        let duration = match self {
             #[cfg(feature = "duckdb")]
             Connection::DuckDB(connection) => connection.run_test_query(record.duckdb.as_ref()),
             Connection::PostGres(connection) => {
                 connection.run_test_query(record.postgresql.as_ref())
             }
             Connection::QLever(connection) => connection.run_test_query(record.sparql.as_ref()),
+        };
+        duration.map_err(|e| format!("query {} failed: {e}", record.name).into())
+    }
+
+    /// Configuration of the engine that the results depend on, as (name, value). Empty if there is none to record.
+    pub fn settings(&mut self, config: &RunConfig) -> Vec<(String, String)> {
+        match self {
+            #[cfg(feature = "duckdb")]
+            Connection::DuckDB(_) => Vec::new(),
+            Connection::PostGres(connection) => connection.settings(&config.postgres_settings),
+            Connection::QLever(_) => Vec::new(),
+        }
+    }
+
+    /// Executes the query and returns the plan it ran with.
+    pub fn explain(&mut self, record: &TSVRecord) -> Result<QueryPlan, Box<dyn Error>> {
+        match self {
+            #[cfg(feature = "duckdb")]
+            Connection::DuckDB(connection) => connection.explain(record.duckdb.as_ref()),
+            Connection::PostGres(connection) => connection.explain(record.postgresql.as_ref()),
+            Connection::QLever(connection) => connection.explain(record.sparql.as_ref()),
         }
     }
 
     pub fn close(self) -> Result<(), Box<dyn Error>> {
         match self {
-            Connection::QLever(connection) => {
-                connection.stop().expect("qlever stop failed");
-            }
+            Connection::QLever(mut connection) => connection.stop()?,
             #[cfg(feature = "duckdb")]
             Connection::DuckDB(connection) => {
                 connection.close().expect("connection close failed");
             }
-            Connection::PostGres(connection) => {
-                drop(connection);
-            }
+            Connection::PostGres(mut connection) => connection.close()?,
         }
         Ok(())
     }
@@ -303,17 +781,18 @@ impl TestResult {
 }
 
 fn run_test(
-    filename: &String,
+    filename: &str,
+    schema: SchemaVariant,
     iterations: usize,
     connection: &mut Connection,
 ) -> Result<Vec<TestResult>, Box<dyn Error>> {
-    let queries = read_test_file(filename.as_str())?;
+    let queries = read_test_file(filename, schema)?;
     let mut results: Vec<Vec<u128>> = vec![Vec::new(); queries.len()];
     for _ in 0..iterations {
         clear_cache().expect("Failed to clear cache");
         // Run Queries
         for (id, record) in queries.iter().enumerate() {
-            let result = connection.run_test_query(record);
+            let result = connection.run_test_query(record)?;
             results[id].push(result)
         }
     }
@@ -329,6 +808,19 @@ fn run_test(
     Ok(results)
 }
 
+/// Captures the plan of every query of the file. Queries whose plan can't be captured are reported and left out.
+fn capture_plans(filename: &str, schema: SchemaVariant, connection: &mut Connection, results_name: &str) -> Result<(), Box<dyn Error>> {
+    let queries = read_test_file(filename, schema)?;
+    let mut plans = Vec::new();
+    for (id, record) in queries.iter().enumerate() {
+        match connection.explain(record) {
+            Ok(plan) => plans.push((id, record.name.as_str(), plan)),
+            Err(e) => println!("Failed to capture the plan of {}: {e}", record.name),
+        }
+    }
+    plans::write_plans(&plans, results_name)
+}
+
 fn clear_cache() -> Result<(), Box<dyn Error>> {
     // Clear Cache
     let _ = Command::new("bash")
@@ -352,6 +844,20 @@ fn write_results(results: &Vec<TestResult>, filename: String) -> Result<(), Box<
     Ok(())
 }
 
+fn write_settings(settings: &[(String, String)], filename: String) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_writer(File::create(filename.as_str())?);
+
+    writer.write_record(["name", "value"])?;
+    for (name, value) in settings {
+        writer.write_record([name, value])?;
+    }
+
+    Ok(())
+}
+
 fn write_results_aggregated(
     results: &Vec<TestResult>,
     filename: String,
@@ -361,7 +867,7 @@ fn write_results_aggregated(
         .has_headers(true)
         .from_writer(File::create(filename.as_str())?);
 
-    writer.write_record(&["id", "min", "median", "mode", "avg", "max"])?;
+    writer.write_record(["id", "min", "median", "mode", "avg", "max"])?;
     // Aggregate
     for result in results {
         let mut numbers = result.results.clone();
@@ -409,7 +915,7 @@ async fn download_dblp_data(filename: String) -> Result<(), Box<dyn Error>> {
 
     let stream = response
         .bytes_stream()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+        .map_err(std::io::Error::other);
 
     let reader = StreamReader::new(stream);
 
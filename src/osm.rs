@@ -0,0 +1,640 @@
+use flate2::read::ZlibDecoder;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+
+/// Raw OSM table, the layout DuckDB's ST_ReadOSM produces, so queries against it run on both SQL engines.
+/// Tags are an hstore, which Postgres subscripts like DuckDB subscripts its maps. The table of an earlier load is replaced.
+pub const RAW_OSM_SQL: &str = "DROP TABLE IF EXISTS osm;
+DROP TYPE IF EXISTS osm_kind;
+DROP TYPE IF EXISTS osm_ref_type;
+CREATE TYPE osm_kind AS ENUM ('node', 'way', 'relation', 'changeset');
+CREATE TYPE osm_ref_type AS ENUM ('node', 'way', 'relation');
+CREATE TABLE osm(
+    kind osm_kind NOT NULL,
+    id BIGINT NOT NULL,
+    tags hstore,
+    refs BIGINT[],
+    lat DOUBLE PRECISION,
+    lon DOUBLE PRECISION,
+    ref_roles TEXT[],
+    ref_types osm_ref_type[]
+);
+";
+
+/// Columns of the raw OSM table in the order `OsmElement::copy_line` writes them.
+pub const RAW_OSM_COLUMNS: [&str; 8] = ["kind", "id", "tags", "refs", "lat", "lon", "ref_roles", "ref_types"];
+
+/// How Postgres loads an OSM extract.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OsmLoader {
+    /// osm2pgsql with its planet_osm_* tables.
+    Osm2pgsql,
+    /// The built-in PBF reader with the raw OSM table DuckDB loads too.
+    Raw,
+}
+
+impl OsmLoader {
+    pub const NAMES: [&'static str; 2] = ["osm2pgsql", "raw"];
+
+    pub fn from_name(name: &str) -> Option<OsmLoader> {
+        match name {
+            "osm2pgsql" => Some(OsmLoader::Osm2pgsql),
+            "raw" => Some(OsmLoader::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// Largest blob the format allows, anything bigger means the file is corrupt.
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OsmKind {
+    Node,
+    Way,
+    Relation,
+}
+
+impl OsmKind {
+    fn name(&self) -> &'static str {
+        match self {
+            OsmKind::Node => "node",
+            OsmKind::Way => "way",
+            OsmKind::Relation => "relation",
+        }
+    }
+}
+
+/// A node, way or relation with the columns of the raw OSM table. Nodes have coordinates, ways and relations
+/// reference other elements, relations also with a role and the kind of each member.
+#[derive(Debug)]
+pub struct OsmElement {
+    pub kind: OsmKind,
+    pub id: i64,
+    pub tags: Vec<(String, String)>,
+    pub refs: Vec<i64>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub ref_roles: Vec<String>,
+    pub ref_types: Vec<OsmKind>,
+}
+
+impl OsmElement {
+    /// The element as a line of COPY's text format. Empty tags and references are NULL, like in ST_ReadOSM.
+    pub fn copy_line(&self) -> String {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}=>{}", quote(key), quote(value)))
+            .collect();
+        let refs: Vec<String> = self.refs.iter().map(i64::to_string).collect();
+        let roles: Vec<String> = self.ref_roles.iter().map(|role| quote(role)).collect();
+        let types: Vec<&str> = self.ref_types.iter().map(OsmKind::name).collect();
+        let relation = self.kind == OsmKind::Relation;
+        let fields = [
+            Some(self.kind.name().to_string()),
+            Some(self.id.to_string()),
+            (!tags.is_empty()).then(|| tags.join(", ")),
+            (self.kind != OsmKind::Node).then(|| format!("{{{}}}", refs.join(","))),
+            self.lat.map(|lat| lat.to_string()),
+            self.lon.map(|lon| lon.to_string()),
+            relation.then(|| format!("{{{}}}", roles.join(","))),
+            relation.then(|| format!("{{{}}}", types.join(","))),
+        ];
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|field| field.map_or_else(|| String::from("\\N"), |field| escape_copy(&field)))
+            .collect();
+        fields.join("\t") + "\n"
+    }
+}
+
+/// Quotes a string as hstore and array literals expect it.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escapes a field for COPY's text format.
+fn escape_copy(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Reads the nodes, ways and relations of an OSM PBF file in file order. Only uncompressed and zlib compressed
+/// blobs are supported, which is what the Geofabrik extracts use.
+pub fn read_pbf(path: &str, mut visit: impl FnMut(OsmElement) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(path)?);
+    loop {
+        let mut length = [0u8; 4];
+        match file.read_exact(&mut length) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let header = read_bytes(&mut file, u32::from_be_bytes(length) as usize)?;
+        let mut blob_type = String::new();
+        let mut blob_size = 0;
+        for field in Message::new(&header) {
+            match field? {
+                (1, Value::Bytes(bytes)) => blob_type = String::from_utf8(bytes.to_vec())?,
+                (3, Value::Varint(size)) => blob_size = size as usize,
+                _ => (),
+            }
+        }
+        let blob = read_bytes(&mut file, blob_size)?;
+        match blob_type.as_str() {
+            "OSMHeader" => check_header(&decompress(&blob)?)?,
+            "OSMData" => read_block(&decompress(&blob)?, &mut visit)?,
+            // Unknown blobs may be skipped
+            _ => (),
+        }
+    }
+}
+
+fn read_bytes(file: &mut impl Read, size: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    if size > MAX_BLOB_SIZE {
+        return Err(format!("PBF blob of {size} bytes is larger than the format allows").into());
+    }
+    let mut bytes = vec![0; size];
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn decompress(blob: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    for field in Message::new(blob) {
+        match field? {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3, Value::Bytes(zlib)) => {
+                let mut data = Vec::new();
+                ZlibDecoder::new(zlib).read_to_end(&mut data)?;
+                return Ok(data);
+            }
+            (number @ 4..=7, Value::Bytes(_)) => {
+                return Err(format!("PBF blobs compressed with field {number} aren't supported, only zlib").into());
+            }
+            _ => (),
+        }
+    }
+    Err("PBF blob without data".into())
+}
+
+/// Fails on files that need features the reader doesn't have.
+fn check_header(header: &[u8]) -> Result<(), Box<dyn Error>> {
+    for field in Message::new(header) {
+        if let (4, Value::Bytes(feature)) = field? {
+            let feature = String::from_utf8_lossy(feature);
+            if !matches!(feature.as_ref(), "OsmSchema-V0.6" | "DenseNodes") {
+                return Err(format!("PBF file needs the unsupported feature {feature}").into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Coordinates of a block are stored as multiples of its granularity in nanodegrees, plus an offset.
+struct Coordinates {
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl Coordinates {
+    fn lat(&self, lat: i64) -> f64 {
+        (self.lat_offset + self.granularity * lat) as f64 / 1e9
+    }
+
+    fn lon(&self, lon: i64) -> f64 {
+        (self.lon_offset + self.granularity * lon) as f64 / 1e9
+    }
+}
+
+fn read_block(block: &[u8], visit: &mut impl FnMut(OsmElement) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let mut strings = Vec::new();
+    let mut groups = Vec::new();
+    let mut coordinates = Coordinates { granularity: 100, lat_offset: 0, lon_offset: 0 };
+    for field in Message::new(block) {
+        match field? {
+            (1, Value::Bytes(table)) => {
+                for string in Message::new(table) {
+                    if let (1, Value::Bytes(string)) = string? {
+                        strings.push(String::from_utf8_lossy(string).into_owned());
+                    }
+                }
+            }
+            (2, Value::Bytes(group)) => groups.push(group),
+            (17, Value::Varint(granularity)) => coordinates.granularity = granularity as i64,
+            (19, Value::Varint(offset)) => coordinates.lat_offset = offset as i64,
+            (20, Value::Varint(offset)) => coordinates.lon_offset = offset as i64,
+            _ => (),
+        }
+    }
+    let string = |index: u64| -> Result<String, Box<dyn Error>> {
+        strings.get(index as usize).cloned().ok_or_else(|| format!("string {index} isn't in the string table").into())
+    };
+    let tags = |keys: &[u64], values: &[u64]| -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        keys.iter().zip(values).map(|(key, value)| Ok((string(*key)?, string(*value)?))).collect()
+    };
+    for group in groups {
+        for field in Message::new(group) {
+            let (number, value) = field?;
+            let Value::Bytes(element) = value else { continue };
+            match number {
+                1 => {
+                    let node = Fields::read(element)?;
+                    visit(OsmElement {
+                        kind: OsmKind::Node,
+                        id: zigzag(node.single(1)),
+                        tags: tags(&node.repeated(2), &node.repeated(3))?,
+                        refs: Vec::new(),
+                        lat: Some(coordinates.lat(zigzag(node.single(8)))),
+                        lon: Some(coordinates.lon(zigzag(node.single(9)))),
+                        ref_roles: Vec::new(),
+                        ref_types: Vec::new(),
+                    })?;
+                }
+                2 => read_dense_nodes(element, &coordinates, &string, visit)?,
+                3 => {
+                    let way = Fields::read(element)?;
+                    visit(OsmElement {
+                        kind: OsmKind::Way,
+                        id: way.single(1) as i64,
+                        tags: tags(&way.repeated(2), &way.repeated(3))?,
+                        refs: deltas(&way.repeated(8)),
+                        lat: None,
+                        lon: None,
+                        ref_roles: Vec::new(),
+                        ref_types: Vec::new(),
+                    })?;
+                }
+                4 => {
+                    let relation = Fields::read(element)?;
+                    let ref_types = relation
+                        .repeated(10)
+                        .into_iter()
+                        .map(|kind| match kind {
+                            0 => Ok(OsmKind::Node),
+                            1 => Ok(OsmKind::Way),
+                            2 => Ok(OsmKind::Relation),
+                            kind => Err(format!("relation member of unknown type {kind}")),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    visit(OsmElement {
+                        kind: OsmKind::Relation,
+                        id: relation.single(1) as i64,
+                        tags: tags(&relation.repeated(2), &relation.repeated(3))?,
+                        refs: deltas(&relation.repeated(9)),
+                        lat: None,
+                        lon: None,
+                        ref_roles: relation.repeated(8).into_iter().map(&string).collect::<Result<_, _>>()?,
+                        ref_types,
+                    })?;
+                }
+                // Changesets don't carry any data in extracts
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Dense nodes store their ids and coordinates as deltas and their tags as one list of key and value indices,
+/// where each node's tags end with a 0.
+fn read_dense_nodes(
+    dense: &[u8],
+    coordinates: &Coordinates,
+    string: &impl Fn(u64) -> Result<String, Box<dyn Error>>,
+    visit: &mut impl FnMut(OsmElement) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let fields = Fields::read(dense)?;
+    let ids = deltas(&fields.repeated(1));
+    let lats = deltas(&fields.repeated(8));
+    let lons = deltas(&fields.repeated(9));
+    if lats.len() != ids.len() || lons.len() != ids.len() {
+        return Err("dense nodes with a different number of ids and coordinates".into());
+    }
+    let mut keys_values = fields.repeated(10).into_iter();
+    for ((id, lat), lon) in ids.into_iter().zip(lats).zip(lons) {
+        let mut tags = Vec::new();
+        while let Some(key) = keys_values.next().filter(|key| *key != 0) {
+            let value = keys_values.next().ok_or("dense node tag without a value")?;
+            tags.push((string(key)?, string(value)?));
+        }
+        visit(OsmElement {
+            kind: OsmKind::Node,
+            id,
+            tags,
+            refs: Vec::new(),
+            lat: Some(coordinates.lat(lat)),
+            lon: Some(coordinates.lon(lon)),
+            ref_roles: Vec::new(),
+            ref_types: Vec::new(),
+        })?;
+    }
+    Ok(())
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Sums up delta coded sint64 values.
+fn deltas(values: &[u64]) -> Vec<i64> {
+    values
+        .iter()
+        .scan(0i64, |sum, value| {
+            *sum += zigzag(*value);
+            Some(*sum)
+        })
+        .collect()
+}
+
+/// Value of a protobuf field. Fixed size values aren't used by the OSM messages and only skipped.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the fields of a protobuf message as (field number, value).
+struct Message<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn new(data: &'a [u8]) -> Message<'a> {
+        Message { data }
+    }
+
+    fn varint(&mut self) -> Result<u64, Box<dyn Error>> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.data.split_first().ok_or("truncated protobuf varint")?;
+            self.data = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("protobuf varint longer than 64 bits".into())
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if size > self.data.len() {
+            return Err("truncated protobuf field".into());
+        }
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>), Box<dyn Error>> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.take(8).map(|_| Value::Fixed)?,
+            2 => {
+                let size = self.varint()? as usize;
+                Value::Bytes(self.take(size)?)
+            }
+            5 => self.take(4).map(|_| Value::Fixed)?,
+            wire_type => return Err(format!("unsupported protobuf wire type {wire_type}").into()),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Message<'a> {
+    type Item = Result<(u64, Value<'a>), Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // Nothing after a malformed field can be read
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
+
+/// The varint fields of a message by number, repeated ones in order whether they are packed or not.
+struct Fields {
+    values: Vec<(u64, u64)>,
+}
+
+impl Fields {
+    /// Reads the varint fields of a message. Packed fields are only unpacked for the field numbers the OSM
+    /// elements use for them, everything else length delimited is skipped.
+    fn read(message: &[u8]) -> Result<Fields, Box<dyn Error>> {
+        let mut values = Vec::new();
+        for field in Message::new(message) {
+            match field? {
+                (number, Value::Varint(value)) => values.push((number, value)),
+                (number @ (1..=3 | 8..=10), Value::Bytes(packed)) => {
+                    let mut packed = Message::new(packed);
+                    while !packed.data.is_empty() {
+                        values.push((number, packed.varint()?));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(Fields { values })
+    }
+
+    fn single(&self, number: u64) -> u64 {
+        self.values.iter().find(|(field, _)| *field == number).map_or(0, |(_, value)| *value)
+    }
+
+    fn repeated(&self, number: u64) -> Vec<u64> {
+        self.values.iter().filter(|(field, _)| *field == number).map(|(_, value)| *value).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::scratch_dir;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+
+    /// Tag value that needs escaping both inside hstore and array literals and in COPY's text format.
+    const ESCAPED: &str = "Qu\"o\\te\tTab\nLine";
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn number(number: u64, value: u64) -> Vec<u8> {
+        [varint(number << 3), varint(value)].concat()
+    }
+
+    fn bytes(number: u64, data: &[u8]) -> Vec<u8> {
+        [varint(number << 3 | 2), varint(data.len() as u64), data.to_vec()].concat()
+    }
+
+    fn packed(field: u64, values: &[u64]) -> Vec<u8> {
+        bytes(field, &values.iter().flat_map(|value| varint(*value)).collect::<Vec<u8>>())
+    }
+
+    fn sint(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    fn delta_coded(values: &[i64]) -> Vec<u64> {
+        let mut previous = 0;
+        values
+            .iter()
+            .map(|value| {
+                let delta = sint(value - previous);
+                previous = *value;
+                delta
+            })
+            .collect()
+    }
+
+    /// A blob with its header, zlib compressed or raw.
+    fn blob(kind: &str, data: &[u8], zlib: bool) -> Vec<u8> {
+        let blob = if zlib {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            [number(2, data.len() as u64), bytes(3, &encoder.finish().unwrap())].concat()
+        } else {
+            bytes(1, data)
+        };
+        let header = [bytes(1, kind.as_bytes()), number(3, blob.len() as u64)].concat();
+        [(header.len() as u32).to_be_bytes().to_vec(), header, blob].concat()
+    }
+
+    fn header(features: &[&str]) -> Vec<u8> {
+        blob("OSMHeader", &features.iter().flat_map(|feature| bytes(4, feature.as_bytes())).collect::<Vec<u8>>(), false)
+    }
+
+    /// Three dense nodes, a plain node, a way and a relation in a block with its own granularity and offsets.
+    fn sample_pbf() -> Vec<u8> {
+        let strings = [
+            "", "name", ESCAPED, "highway", "residential", "outer", "inner", "type", "multipolygon", "amenity", "bench",
+        ];
+        let string_table: Vec<u8> = strings.iter().flat_map(|string| bytes(1, string.as_bytes())).collect();
+        let dense = [
+            packed(1, &delta_coded(&[10, 12, 11])),
+            packed(8, &delta_coded(&[48_500_000, 48_500_100, -1_000])),
+            packed(9, &delta_coded(&[7_800_000, 7_799_900, 5])),
+            packed(10, &[1, 2, 0, 0, 9, 10, 0]),
+        ]
+        .concat();
+        let node = [number(1, sint(-5)), packed(2, &[3]), packed(3, &[4]), number(8, sint(1)), number(9, sint(-1))].concat();
+        let way = [number(1, 100), packed(2, &[1]), packed(3, &[2]), packed(8, &delta_coded(&[10, 12, 11]))].concat();
+        let relation = [
+            number(1, 200),
+            packed(2, &[7]),
+            packed(3, &[8]),
+            packed(8, &[5, 2, 0]),
+            packed(9, &delta_coded(&[100, 10, 200])),
+            packed(10, &[1, 0, 2]),
+        ]
+        .concat();
+        let block = [
+            bytes(1, &string_table),
+            bytes(2, &[bytes(2, &dense), bytes(1, &node)].concat()),
+            bytes(2, &[bytes(3, &way), bytes(4, &relation)].concat()),
+            number(17, 1000),
+            number(19, 5000),
+            number(20, -3000i64 as u64),
+        ]
+        .concat();
+        [header(&["OsmSchema-V0.6", "DenseNodes"]), blob("OSMData", &block, true)].concat()
+    }
+
+    fn read_sample(name: &str, pbf: &[u8]) -> Result<Vec<OsmElement>, Box<dyn Error>> {
+        let path = format!("{}/sample.osm.pbf", scratch_dir(name));
+        fs::write(&path, pbf).unwrap();
+        let mut elements = Vec::new();
+        read_pbf(&path, |element| {
+            elements.push(element);
+            Ok(())
+        })?;
+        Ok(elements)
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn decodes_dense_nodes() {
+        let elements = read_sample("osm_nodes", &sample_pbf()).unwrap();
+        let nodes: Vec<(i64, Option<f64>, Option<f64>)> = elements
+            .iter()
+            .filter(|element| element.kind == OsmKind::Node)
+            .map(|node| (node.id, node.lat, node.lon))
+            .collect();
+        // Coordinates are offset + granularity * value in nanodegrees
+        assert_eq!(
+            nodes,
+            vec![
+                (10, Some(48.500005), Some(7.799997)),
+                (12, Some(48.500105), Some(7.799897)),
+                (11, Some(-0.000995), Some(0.000002)),
+                (-5, Some(0.000006), Some(-0.000004)),
+            ]
+        );
+        assert_eq!(elements[0].tags, tags(&[("name", ESCAPED)]));
+        assert!(elements[1].tags.is_empty());
+        assert_eq!(elements[2].tags, tags(&[("amenity", "bench")]));
+        assert_eq!(elements[3].tags, tags(&[("highway", "residential")]));
+    }
+
+    #[test]
+    fn decodes_ways_and_relations() {
+        let elements = read_sample("osm_members", &sample_pbf()).unwrap();
+        let way = &elements[4];
+        assert_eq!((way.kind, way.id, way.refs.as_slice()), (OsmKind::Way, 100, [10, 12, 11].as_slice()));
+        assert_eq!(way.tags, tags(&[("name", ESCAPED)]));
+        let relation = &elements[5];
+        assert_eq!((relation.kind, relation.id), (OsmKind::Relation, 200));
+        assert_eq!(relation.refs, vec![100, 10, 200]);
+        assert_eq!(relation.ref_roles, vec!["outer", ESCAPED, ""]);
+        assert_eq!(relation.ref_types, vec![OsmKind::Way, OsmKind::Node, OsmKind::Relation]);
+        assert_eq!(relation.tags, tags(&[("type", "multipolygon")]));
+    }
+
+    #[test]
+    fn escapes_copy_lines() {
+        let elements = read_sample("osm_copy", &sample_pbf()).unwrap();
+        let line = |fields: [&str; 8]| fields.join("\t") + "\n";
+        let escaped = r#""name"=>"Qu\\"o\\\\te\tTab\nLine""#;
+        assert_eq!(elements[0].copy_line(), line(["node", "10", escaped, r"\N", "48.500005", "7.799997", r"\N", r"\N"]));
+        assert_eq!(elements[1].copy_line(), line(["node", "12", r"\N", r"\N", "48.500105", "7.799897", r"\N", r"\N"]));
+        assert_eq!(elements[4].copy_line(), line(["way", "100", escaped, "{10,12,11}", r"\N", r"\N", r"\N", r"\N"]));
+        assert_eq!(
+            elements[5].copy_line(),
+            line([
+                "relation",
+                "200",
+                r#""type"=>"multipolygon""#,
+                "{100,10,200}",
+                r"\N",
+                r"\N",
+                r#"{"outer","Qu\\"o\\\\te\tTab\nLine",""}"#,
+                "{way,node,relation}",
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_features() {
+        let pbf = header(&["OsmSchema-V0.6", "HistoricalInformation"]);
+        let error = read_sample("osm_features", &pbf).unwrap_err();
+        assert_eq!(error.to_string(), "PBF file needs the unsupported feature HistoricalInformation");
+    }
+}
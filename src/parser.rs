@@ -1,20 +1,68 @@
-use crate::{AFFILIATIONS_FILE, ALIAS_FILE, AUTHOR_FILE, AUTHOR_WEBSITES_FILE, EDITOR_FILE, PUBLICATION_AUTHORS_FILE, PUBLICATION_EDITOR_FILE, PUBLICATION_FILE, PUBLISHER_FILE, REFERENCE_FILE, RESOURCES_FILE, VENUE_FILE};
-use csv::WriterBuilder;
-use quick_xml::events::attributes::Attribute;
+use crate::formats::{epoch_days, InvalidDate, OutputFormat};
+use crate::rdf::{RdfFormat, RdfWriter};
+use crate::SCHEMA_FILE;
+use crate::tables::{create_tables_dblp_sql, file_in, read_rows, AffiliationRow, AliasRow, AuthorIdentifierRow, AuthorNoteRow, AuthorRow, AuthorWebsiteRow, AwardRow, EditorRow, PublicationAuthorRow, PublicationEditorRow, PublicationRow, PublisherRow, ReferenceRow, RejectRow, ResourceRow, TableWriter, UnameRow, VenueRow};
+use flate2::read::MultiGzDecoder;
+use quick_xml::events::attributes::AttrError;
 use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
 use regex::Regex;
-use serde::ser::SerializeStruct;
-use serde::{Deserialize, Serialize, Serializer};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
-use std::fmt::Display;
-use std::fs::{File, OpenOptions};
-use std::io::BufReader;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::sync::LazyLock;
+
+/// How the parser treats records it can't read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    /// Log the record to the reject file and continue.
+    Lenient,
+    /// Stop at the first malformed record.
+    Strict,
+}
+
+/// What a parse produced, printed once it is done.
+#[derive(Debug, Default)]
+pub struct ParseStats {
+    /// Records written per publication type, persons are counted separately.
+    pub records: BTreeMap<String, usize>,
+    pub rejects: BTreeMap<&'static str, usize>,
+    /// Author occurrences whose name matched no person record and were left out of PublicationAuthors.
+    pub dropped_authors: usize,
+    /// Author occurrences whose name matched no person record and were linked to a synthesized author.
+    pub synthesized_authors: usize,
+    /// Authors created for names without a person record.
+    pub synthesized_persons: usize,
+}
+
+impl fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Records:")?;
+        for (record_type, count) in self.records.iter() {
+            writeln!(f, "  {record_type}: {count}")?;
+        }
+        writeln!(f, "Rejected records: {}", self.rejects.values().sum::<usize>())?;
+        for (reason, count) in self.rejects.iter() {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+        write!(
+            f,
+            "Unresolved authors: {} dropped, {} linked to {} synthesized authors",
+            self.dropped_authors, self.synthesized_authors, self.synthesized_persons
+        )
+    }
+}
 
 pub struct Parser {
-    reader: Reader<BufReader<File>>,
+    reader: Reader<Box<dyn BufRead>>,
+    dir: String,
+    mode: ParseMode,
+    synthesize_authors: bool,
+    stats: ParseStats,
     next_venue_id: usize,
     next_publisher_id: usize,
     next_editor_id: usize,
@@ -23,21 +71,39 @@ pub struct Parser {
     next_author_website_id: usize,
     next_affiliation_id: usize,
     next_alias_id: usize,
+    next_award_id: usize,
+    next_uname_id: usize,
+    next_author_note_id: usize,
+    next_identifier_id: usize,
     venue_map: HashMap<(String, String), usize>,
     publisher_map: HashMap<String, usize>,
     editor_map: HashMap<String, usize>,
     author_map: HashMap<(String, usize), usize>,
+    previous_authors: HashMap<(String, usize), usize>, // keys of a previous parse, see seed_from
+    identifier_set: HashSet<(usize, String, String)>, // author_id, scheme, identifier
+    person_keys: HashMap<usize, String>, // author key -> homepage key, only kept for the RDF output
+    synthesized_keys: HashSet<usize>, // keys of authors created for names without a person record
     writer: WriteManager,
+    rdf: Option<RdfWriter>,
     publications: Vec<Publication>,
 }
 
 impl Parser {
-    pub fn new(file: &str) -> Parser {
-        let file = File::open(file).unwrap();
-        let mut reader = Reader::from_reader(BufReader::new(file));
+    /// Parses the DBLP xml `file`, gzipped if it ends in .gz, into the tables in `dir`.
+    pub fn new(file: &str, dir: &str, mode: ParseMode, format: OutputFormat) -> Result<Parser, ParseError> {
+        let input: Box<dyn BufRead> = if file.ends_with(".gz") {
+            Box::new(BufReader::new(MultiGzDecoder::new(File::open(file)?)))
+        } else {
+            Box::new(BufReader::new(File::open(file)?))
+        };
+        let mut reader = Reader::from_reader(input);
         reader.config_mut().trim_text(true);
-        Parser { 
+        Ok(Parser {
             reader,
+            dir: dir.to_string(),
+            mode,
+            synthesize_authors: false,
+            stats: Default::default(),
             next_venue_id: 0,
             next_publisher_id: 0,
             next_editor_id: 0,
@@ -46,35 +112,129 @@ impl Parser {
             next_author_website_id: 0,
             next_affiliation_id: 0,
             next_alias_id: 0,
+            next_award_id: 0,
+            next_uname_id: 0,
+            next_author_note_id: 0,
+            next_identifier_id: 0,
             venue_map: Default::default(),
             publisher_map: Default::default(),
             editor_map: Default::default(),
             author_map: Default::default(),
-            writer: WriteManager::new(),
+            previous_authors: Default::default(),
+            identifier_set: Default::default(),
+            person_keys: Default::default(),
+            synthesized_keys: Default::default(),
+            writer: WriteManager::new(dir, format)?,
+            rdf: None,
             publications: vec![],
-        }
+        })
     }
     
-    pub fn run(&mut self) {
+    /// Continues the ids of a previous parse in `dir`. Persons, venues, publishers and editors that are
+    /// still in the dump keep their keys, and all other rows get ids above the ones of the previous parse,
+    /// so they don't collide with rows already loaded from it.
+    pub fn seed_from(&mut self, dir: &str) -> Result<(), ParseError> {
+        // Lookup rows are only ever added, so the previous ones are carried over into this parse
+        for venue in read_rows::<VenueRow>(dir)? {
+            self.next_venue_id = self.next_venue_id.max(venue.id + 1);
+            self.venue_map.insert((venue.name.clone().unwrap_or_default(), venue.r#type.clone().unwrap_or_default()), venue.id);
+            self.writer.venues.write(venue)?;
+        }
+        for publisher in read_rows::<PublisherRow>(dir)? {
+            self.next_publisher_id = self.next_publisher_id.max(publisher.id + 1);
+            self.publisher_map.insert(publisher.name.clone().unwrap_or_default(), publisher.id);
+            self.writer.publishers.write(publisher)?;
+        }
+        for editor in read_rows::<EditorRow>(dir)? {
+            self.next_editor_id = self.next_editor_id.max(editor.id + 1);
+            self.editor_map.insert(editor.name.clone(), editor.id);
+            self.writer.editors.write(editor)?;
+        }
+        // Persons get their key when they are read again, so removed persons don't resolve any authors
+        for author in read_rows::<AuthorRow>(dir)? {
+            self.next_author_id = self.next_author_id.max(author.key + 1);
+            self.previous_authors.insert((author.name, author.id), author.key);
+        }
+        self.next_resource_id = next_id(read_rows::<ResourceRow>(dir)?.iter().map(|row| row.id));
+        self.next_author_website_id = next_id(read_rows::<AuthorWebsiteRow>(dir)?.iter().map(|row| row.id));
+        self.next_affiliation_id = next_id(read_rows::<AffiliationRow>(dir)?.iter().map(|row| row.id));
+        self.next_alias_id = next_id(read_rows::<AliasRow>(dir)?.iter().map(|row| row.id));
+        self.next_award_id = next_id(read_rows::<AwardRow>(dir)?.iter().map(|row| row.id));
+        self.next_uname_id = next_id(read_rows::<UnameRow>(dir)?.iter().map(|row| row.id));
+        self.next_author_note_id = next_id(read_rows::<AuthorNoteRow>(dir)?.iter().map(|row| row.id));
+        self.next_identifier_id = next_id(read_rows::<AuthorIdentifierRow>(dir)?.iter().map(|row| row.id));
+        Ok(())
+    }
+
+    /// Also writes the records as RDF following the dblp schema, so QLever can load the same data.
+    pub fn write_rdf(&mut self, format: RdfFormat) -> Result<(), ParseError> {
+        self.rdf = Some(RdfWriter::new(&file_in(&self.dir, format.file()), format)?);
+        Ok(())
+    }
+
+    /// Creates an author for every name on a publication that matches no person record, instead of
+    /// dropping the occurrence. The RDF output keeps these occurrences as signatures either way.
+    pub fn synthesize_authors(&mut self) {
+        self.synthesize_authors = true;
+    }
+
+    pub fn run(&mut self) -> Result<ParseStats, ParseError> {
         let mut buf = Vec::new();
         loop {
+            let offset = self.reader.buffer_position();
             match self.reader.read_event_into(&mut buf) {
                 Ok(Event::Start(e)) if matches!(e.name().as_ref(), b"dblp") => {} // Skip if the tag is dblp
-                Ok(Event::Start(e)) if Parser::is_person(&e) => self.read_person(&e).unwrap(),
-                Ok(Event::Start(e)) if Parser::is_publication(e.name().as_ref()) => self.read_publication(&e).unwrap(),
+                Ok(Event::Start(e)) if Parser::is_person(&e) => {
+                    match self.read_person(&e) {
+                        Ok(person) => self.write_person(person)?,
+                        Err(reason) => self.reject(record_key(&e), reason, offset)?,
+                    }
+                }
+                Ok(Event::Start(e)) if Parser::is_publication(e.name().as_ref()) => {
+                    if let Err(reason) = self.read_publication(&e) {
+                        self.reject(record_key(&e), reason, offset)?;
+                    }
+                }
                 Ok(Event::Eof) => break,
-                Err(e) => panic!(
-                    "Error at position {}: {:?}",
-                    self.reader.buffer_position(),
-                    e
-                ),
+                Err(e) if self.mode == ParseMode::Strict => return Err(ParseError::Xml { offset, source: e }),
+                Err(e) => {
+                    self.reject(None, e.into(), offset)?;
+                    if self.reader.buffer_position() == offset {
+                        return Err(ParseError::Stuck { offset });
+                    }
+                }
                 _ => (),
             }
             buf.clear();
         }
-        self.writer.finalize();
-        self.write_publications();
-        self.writer.finalize();
+        self.writer.finalize()?;
+        self.write_publications()?;
+        self.writer.finish()?;
+        if let Some(rdf) = self.rdf.as_mut() {
+            rdf.flush();
+        }
+        self.stats.synthesized_persons = self.synthesized_keys.len();
+        Ok(std::mem::take(&mut self.stats))
+    }
+
+    fn reject(&mut self, key: Option<String>, reason: RejectReason, offset: u64) -> Result<(), ParseError> {
+        if self.mode == ParseMode::Strict {
+            return Err(ParseError::Rejected { key, reason, offset });
+        }
+        *self.stats.rejects.entry(reason.name()).or_insert(0) += 1;
+        self.writer.rejects.write(RejectRow {
+            key,
+            reason: reason.name().to_string(),
+            detail: reason.to_string(),
+            offset,
+        })?;
+        Ok(())
+    }
+
+    /// Skips the rest of a record after an error, so parsing continues at the next one.
+    fn skip_record(&mut self, start: &BytesStart) {
+        // An error here resurfaces on the next read in run
+        let _ = self.reader.read_to_end_into(start.to_end().name(), &mut Vec::new());
     }
 
     fn is_publication(tag: &[u8]) -> bool {
@@ -93,29 +253,30 @@ impl Parser {
 
     fn is_person(e: &BytesStart) -> bool {
         let tag = e.name();
-        if let Some(attr) = e.try_get_attribute("key").unwrap() {
+        if let Ok(Some(attr)) = e.try_get_attribute("key") {
             let key = attr.value.as_ref();
             return matches!(tag.as_ref(), b"www") && key.starts_with(b"homepages/")
         }
         false
     }
 
-    fn read_publication(&mut self, eve: &BytesStart) -> Result<(), Box<dyn Error>> {
-        let mut buf = Vec::new();
+    fn read_publication(&mut self, eve: &BytesStart) -> Result<(), RejectReason> {
         let mut publication = Publication::new();
+        if let Err(reason) = self.read_publication_fields(eve, &mut publication) {
+            self.skip_record(eve);
+            return Err(reason);
+        }
+        publication.key = self.read_attribute(eve, "key")?.ok_or(RejectReason::MissingAttribute("key"))?;
+        publication.mdate = self.read_attribute(eve, "mdate")?.ok_or(RejectReason::MissingAttribute("mdate"))?;
+        publication.check_constraints()?;
+        *self.stats.records.entry(publication.pubtype.clone()).or_insert(0) += 1;
+        self.publications.push(publication);
+        Ok(())
+    }
+
+    fn read_publication_fields(&mut self, eve: &BytesStart, publication: &mut Publication) -> Result<(), RejectReason> {
+        let mut buf = Vec::new();
         let re_pubkey = Regex::new(r"\S+/\S+/\S+").unwrap();
-        publication.key = String::from(
-            eve.try_get_attribute("key")
-                .unwrap()
-                .unwrap()
-                .decode_and_unescape_value(self.reader.decoder())?,
-        );
-        publication.mdate = String::from(
-            eve.try_get_attribute("mdate")
-                .unwrap()
-                .unwrap()
-                .decode_and_unescape_value(self.reader.decoder())?,
-        );
         publication.pubtype = match eve.local_name().as_ref() {
             b"article" => "article".to_string(),
             b"inproceedings" => "inproceedings".to_string(),
@@ -132,12 +293,8 @@ impl Parser {
                 Ok(Event::Start(e)) => match e.name().as_ref() {
                     //General
                     b"author" => {
-                        let author = self.read_text(&e)?;
-                        let mut person = Person::new();
-                        person.add_name(author.as_str());
-                        if !publication.authors.contains(&person) {
-                            publication.authors.push(person);
-                        }
+                        let author = self.read_contributor(&e)?;
+                        publication.authors.push(author);
                     }
                     b"title" => {
                         publication.title = self.read_text(&e)?;
@@ -152,25 +309,15 @@ impl Parser {
                         publication.pages = Some(self.read_text(&e)?);
                     }
                     b"note" => {
-                        let attr = e
-                            .try_get_attribute("type");
-                        if let Some(attr) = attr.unwrap() { 
-                            let value = attr.decode_and_unescape_value(self.reader.decoder())?;
-                            match value.as_ref() {
-                                "isbn" => {
-                                    publication.isbn = Some(self.read_text(&e)?);
-                                }
-                                _ => publication
-                                    .resources
-                                    .push((String::from(value), self.read_text(&e)?)),
+                        let note_type = self.read_attribute(&e, "type")?.unwrap_or_else(|| String::from("note"));
+                        match note_type.as_str() {
+                            "isbn" => {
+                                publication.isbn = Some(self.read_text(&e)?);
                             }
-                        }
-                        else {
-                            publication
+                            _ => publication
                                 .resources
-                                .push((String::from("note"), self.read_text(&e)?));
+                                .push((note_type, self.read_text(&e)?)),
                         }
-                        
                     }
                     b"number" => {
                         publication.number = Some(self.read_text(&e)?);
@@ -187,7 +334,8 @@ impl Parser {
                         publication.publisher = Some(self.read_text(&e)?);
                     }
                     b"editor" => {
-                        publication.editor.push(self.read_text(&e)?);
+                        let editor = self.read_contributor(&e)?;
+                        publication.editor.push(editor);
                     }
                     b"booktitle" => {
                         publication.book_title = Some(self.read_text(&e)?);
@@ -213,26 +361,19 @@ impl Parser {
                         .resources
                         .push((String::from_utf8_lossy(e.name().as_ref()).into_owned(), self.read_text(&e)?)),
                     _ => {
-                        self.reader
-                            .read_to_end_into(e.to_end().name(), &mut Vec::new())
-                            .unwrap();
+                        self.reader.read_to_end_into(e.to_end().name(), &mut Vec::new())?;
                     } // Skip unknown tags
                 },
                 Ok(Event::End(e)) if e.name().as_ref() == eve.name().as_ref() => break,
-                Ok(Event::Eof) => return Err("Unexpected EOF".into()),
+                Ok(Event::Eof) => return Err(RejectReason::UnexpectedEof),
+                Err(e) => return Err(e.into()),
                 _ => (),
             }
         }
-        if !publication.fulfills_constraints() {
-            println!("{:?}", publication);
-            println!("{:?}", eve);
-        } else {
-            self.publications.push(publication);
-        }
-       Ok(())
+        Ok(())
     }
     
-    fn write_publications(&mut self) {
+    fn write_publications(&mut self) -> io::Result<()> {
         for publication in self.publications.iter_mut() {
             // Venue
             let mut venue_name = None;
@@ -255,82 +396,154 @@ impl Parser {
                 && venue_type.is_some()
                 && !self.venue_map.contains_key(&(venue_name.clone().unwrap(), venue_type.clone().unwrap())) {
                 self.venue_map.insert((venue_name.clone().unwrap(), venue_type.clone().unwrap()), self.next_venue_id);
-                self.writer.write_venue((self.next_venue_id, venue_name.clone(), venue_type.clone()));
+                self.writer.venues.write(VenueRow {
+                    id: self.next_venue_id,
+                    name: venue_name.clone(),
+                    r#type: venue_type.clone(),
+                })?;
                 self.next_venue_id += 1
             }
             // Publisher
             if publication.publisher.is_some()
                 && !self.publisher_map.contains_key(&publication.publisher.clone().unwrap()) {
                 self.publisher_map.insert(publication.publisher.clone().unwrap(), self.next_publisher_id);
-                self.writer.write_publisher((self.next_publisher_id, publication.publisher.clone()));
+                self.writer.publishers.write(PublisherRow {
+                    id: self.next_publisher_id,
+                    name: publication.publisher.clone(),
+                })?;
                 self.next_publisher_id += 1;
             }
             // Editors
-            for editor in publication.editor.iter() {
-                if !self.editor_map.contains_key(editor) {
-                    self.editor_map.insert(editor.clone(), self.next_editor_id);
-                    self.writer.write_editor((self.next_editor_id, editor.clone()));
+            for (index, editor) in publication.editor.iter().enumerate() {
+                if !self.editor_map.contains_key(&editor.raw_name) {
+                    self.editor_map.insert(editor.raw_name.clone(), self.next_editor_id);
+                    self.writer.editors.write(EditorRow {
+                        id: self.next_editor_id,
+                        name: editor.raw_name.clone(),
+                    })?;
                     self.next_editor_id += 1;
                 }
-                self.writer.write_publication_editor((
-                    publication.key.clone(),
-                    self.editor_map.get(editor).unwrap().clone(),
-                ));
+                self.writer.publication_editors.write(PublicationEditorRow {
+                    publication_key: publication.key.clone(),
+                    editor_id: *self.editor_map.get(&editor.raw_name).unwrap(),
+                    position: index + 1,
+                    raw_name: editor.raw_name.clone(),
+                    orcid: editor.orcid.clone(),
+                })?;
             }
             // Publication
-            self.writer.write_publication((
-                publication.key.clone(),
-                publication.mdate.clone(),
-                publication.title.clone(),
-                publication.year.clone(),
-                publication.month.clone(),
-                publication.pubtype.clone(),
-                publication.school.clone(),
-                publication.isbn.clone(),
-                publication.pages.clone(),
-                publication.volume.clone(),
-                publication.number.clone(),
-                self.venue_map.get(&(venue_name.clone().unwrap_or_else(String::new), venue_type.clone().unwrap_or_else(String::new))).copied(),
-                self.publisher_map.get(&publication.publisher.clone().unwrap_or_else(String::new)).copied(),
-            ));
+            self.writer.publications.write(PublicationRow {
+                key: publication.key.clone(),
+                mdate: publication.mdate.clone(),
+                title: publication.title.clone(),
+                year: publication.year,
+                month: publication.month.clone(),
+                r#type: publication.pubtype.clone(),
+                school: publication.school.clone(),
+                isbn: publication.isbn.clone(),
+                pages: publication.pages.clone(),
+                volume: publication.volume.clone(),
+                number: publication.number.clone(),
+                venue_id: self.venue_map.get(&(venue_name.clone().unwrap_or_default(), venue_type.clone().unwrap_or_default())).copied(),
+                publisher_id: self.publisher_map.get(&publication.publisher.clone().unwrap_or_default()).copied(),
+            })?;
+            if let Some(rdf) = self.rdf.as_mut() {
+                let person_key = |contributor: &Contributor| {
+                    self.author_map
+                        .get(&(contributor.name.clone(), contributor.id))
+                        .and_then(|key| self.person_keys.get(key))
+                        .map(String::as_str)
+                };
+                let authors: Vec<Option<&str>> = publication.authors.iter().map(person_key).collect();
+                let editors: Vec<Option<&str>> = publication.editor.iter().map(person_key).collect();
+                rdf.write_publication(publication, &authors, &editors);
+            }
             // Resources
             for resource in publication.resources.iter() {
-                self.writer.write_resource((self.next_resource_id, resource.0.clone(), resource.1.clone(), publication.key.clone()));
+                self.writer.resources.write(ResourceRow {
+                    id: self.next_resource_id,
+                    r#type: resource.0.clone(),
+                    value: resource.1.clone(),
+                    publication_key: publication.key.clone(),
+                })?;
                 self.next_resource_id += 1;
             }
             // References
             for reference in publication.references.iter() {
-                self.writer.write_reference((reference.0.clone(), publication.key.clone(), reference.1.clone()));
+                self.writer.references.write(ReferenceRow {
+                    r#type: reference.0.clone(),
+                    origin_pub: publication.key.clone(),
+                    dest_pub: reference.1.clone(),
+                })?;
             }
             // Authors
-            let mut auth_ids_present: Vec<usize> = Vec::new();
-            for author in publication.authors.iter() {
+            for (index, author) in publication.authors.iter().enumerate() {
                 if !self.author_map.contains_key(&(author.name.clone(), author.id)) {
-                    println!("{:?}",  author);
-                    continue
+                    if !self.synthesize_authors {
+                        self.stats.dropped_authors += 1;
+                        continue
+                    }
+                    // Keep the key of an author synthesized by a previous parse
+                    let key = match self.previous_authors.get(&(author.name.clone(), author.id)) {
+                        Some(key) => *key,
+                        None => {
+                            self.next_author_id += 1;
+                            self.next_author_id - 1
+                        }
+                    };
+                    self.author_map.insert((author.name.clone(), author.id), key);
+                    self.writer.authors.write(AuthorRow {
+                        key,
+                        name: author.name.clone(),
+                        id: author.id,
+                        mdate: None,
+                        orcid: author.orcid.clone(),
+                    })?;
+                    self.synthesized_keys.insert(key);
                 }
                 let auth_id =  self.author_map.get(&(author.name.clone(), author.id)).unwrap();
-                if auth_ids_present.contains(&auth_id) { // Filter out duplicate Author
-                    continue
+                if self.synthesized_keys.contains(auth_id) {
+                    self.stats.synthesized_authors += 1;
                 }
-                self.writer.write_publication_author((
-                    publication.key.clone(),
-                    self.author_map.get(&(author.name.clone(), author.id)).unwrap().clone(),
-                ));
-                auth_ids_present.push(auth_id.clone());
+                self.writer.publication_authors.write(PublicationAuthorRow {
+                    publication_key: publication.key.clone(),
+                    author_id: *auth_id,
+                    position: index + 1,
+                    raw_name: author.raw_name.clone(),
+                    orcid: author.orcid.clone(),
+                })?;
+                // ORCID given on the author occurrence
+                if let Some(orcid) = &author.orcid
+                    && self.identifier_set.insert((*auth_id, String::from("orcid"), orcid.clone())) {
+                    self.writer.author_identifiers.write(AuthorIdentifierRow {
+                        id: self.next_identifier_id,
+                        author_id: *auth_id,
+                        scheme: String::from("orcid"),
+                        identifier: orcid.clone(),
+                        predicate: identifier_predicate("orcid"),
+                    })?;
+                    self.next_identifier_id += 1;
+                }
+            }
         }
+        Ok(())
     }
-}
 
-fn read_person(&mut self, eve: &BytesStart) -> Result<(), Box<dyn Error>> {
-    let mut buf = Vec::new();
-    let mut person = Person::new();
-    person.mdate = String::from(
-        eve.try_get_attribute("mdate")
-            .unwrap()
-            .unwrap()
-            .decode_and_unescape_value(self.reader.decoder())?,
-        );
+    fn read_person(&mut self, eve: &BytesStart) -> Result<Person, RejectReason> {
+        let mut person = Person::new();
+        if let Err(reason) = self.read_person_fields(&mut person) {
+            self.skip_record(eve);
+            return Err(reason);
+        }
+        person.key = self.read_attribute(eve, "key")?.ok_or(RejectReason::MissingAttribute("key"))?;
+        person.mdate = self.read_attribute(eve, "mdate")?.ok_or(RejectReason::MissingAttribute("mdate"))?;
+        person.check_valid()?;
+        *self.stats.records.entry(String::from("person")).or_insert(0) += 1;
+        Ok(person)
+    }
+
+    fn read_person_fields(&mut self, person: &mut Person) -> Result<(), RejectReason> {
+        let mut buf = Vec::new();
         loop {
             match self.reader.read_event_into(&mut buf) {
                 Ok(Event::Start(e)) => match e.name().as_ref() {
@@ -343,85 +556,154 @@ fn read_person(&mut self, eve: &BytesStart) -> Result<(), Box<dyn Error>> {
                         }
                     }
                     b"note" => {
-                        let attr = e
-                            .try_get_attribute("type")
-                            .unwrap()
-                            .unwrap_or(Attribute::from(("type", "ERROR")))
-                            .decode_and_unescape_value(self.reader.decoder())?;
-                        if attr == "affiliation" {
-                            let state = String::from(
-                                e.try_get_attribute("label")
-                                    .unwrap()
-                                    .unwrap_or(Attribute::from(("label", "current")))
-                                    .decode_and_unescape_value(self.reader.decoder())?,
-                            );
-                            person.affiliations.push((String::from(attr), state));
+                        let note_type = self.read_attribute(&e, "type")?.unwrap_or_else(|| String::from("note"));
+                        let label = self.read_attribute(&e, "label")?;
+                        let text = self.read_text(&e)?;
+                        match note_type.as_str() {
+                            "affiliation" => person
+                                .affiliations
+                                .push((text, label.unwrap_or_else(|| String::from("current")))),
+                            "award" => person.awards.push((text, label)),
+                            "uname" => person.unames.push(text),
+                            _ => person.notes.push((note_type, text)), // disambiguation, isnot, ...
                         }
                     }
                     b"url" => {
                         let url = self.read_text(&e)?;
+                        if let Some((scheme, identifier)) = read_identifier(&url) {
+                            if scheme == "orcid" && person.orcid.is_none() {
+                                person.orcid = Some(identifier.clone());
+                            }
+                            person.identifiers.push((scheme, identifier));
+                        }
                         person.urls.push(url);
                     }
                     _ => {
-                        self.reader
-                            .read_to_end_into(e.to_end().name(), &mut Vec::new())
-                            .unwrap();
+                        self.reader.read_to_end_into(e.to_end().name(), &mut Vec::new())?;
                     } // Skip unknown tags
                 },
                 Ok(Event::End(e)) if e.name().as_ref() == b"www" => break,
-                Ok(Event::Eof) => return Err("Unexpected EOF".into()),
+                Ok(Event::Eof) => return Err(RejectReason::UnexpectedEof),
+                Err(e) => return Err(e.into()),
                 _ => (),
             }
         }
-        if person.check_valid() {
-            self.write_person(person);
-        }
         Ok(())
     }
     
-    fn write_person(&mut self, person: Person) -> () {
-        self.author_map.insert((person.name.clone(), person.id), self.next_author_id);
+    fn write_person(&mut self, person: Person) -> io::Result<()> {
+        let key = match self.previous_authors.get(&(person.name.clone(), person.id)) {
+            Some(key) => *key,
+            None => {
+                self.next_author_id += 1;
+                self.next_author_id - 1
+            }
+        };
+        self.author_map.insert((person.name.clone(), person.id), key);
+        if let Some(rdf) = self.rdf.as_mut() {
+            rdf.write_person(&person.key, &person);
+            self.person_keys.insert(key, person.key.clone());
+        }
         // Author
-        self.writer.write_author((
-            self.author_map.get(&(person.name.clone(), person.id)).unwrap().clone(),
-            person.name.clone(),
-            person.id.clone(),
-            person.mdate.clone(),
-            ));
+        self.writer.authors.write(AuthorRow {
+            key: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+            name: person.name.clone(),
+            id: person.id,
+            mdate: Some(person.mdate.clone()),
+            orcid: person.orcid.clone(),
+        })?;
         // Websites
         for website in person.urls.iter() {
-            self.writer.write_author_website((
-                self.next_author_website_id,
-                self.author_map.get(&(person.name.clone(), person.id)).unwrap().clone(),
-                website.clone(),
-                ));
+            self.writer.author_websites.write(AuthorWebsiteRow {
+                id: self.next_author_website_id,
+                author_id: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+                url: website.clone(),
+            })?;
             self.next_author_website_id += 1;
         }
         // Affiliations
         for affiliation in person.affiliations.iter() {
-            self.writer.write_affiliation((
-                self.next_affiliation_id,
-                self.author_map.get(&(person.name.clone(), person.id)).unwrap().clone(),
-                affiliation.0.clone(),
-                affiliation.1.clone(),
-                ));
+            self.writer.affiliations.write(AffiliationRow {
+                id: self.next_affiliation_id,
+                author_id: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+                affiliation: affiliation.0.clone(),
+                r#type: affiliation.1.clone(),
+            })?;
             self.next_affiliation_id += 1;
         }
+        // Identifiers
+        let author_id = *self.author_map.get(&(person.name.clone(), person.id)).unwrap();
+        for identifier in person.identifiers.iter() {
+            if !self.identifier_set.insert((author_id, identifier.0.clone(), identifier.1.clone())) {
+                continue
+            }
+            self.writer.author_identifiers.write(AuthorIdentifierRow {
+                id: self.next_identifier_id,
+                author_id,
+                scheme: identifier.0.clone(),
+                identifier: identifier.1.clone(),
+                predicate: identifier_predicate(&identifier.0),
+            })?;
+            self.next_identifier_id += 1;
+        }
+        // Awards
+        for award in person.awards.iter() {
+            self.writer.awards.write(AwardRow {
+                id: self.next_award_id,
+                author_id: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+                award: award.0.clone(),
+                label: award.1.clone(),
+            })?;
+            self.next_award_id += 1;
+        }
+        // Unames
+        for uname in person.unames.iter() {
+            self.writer.unames.write(UnameRow {
+                id: self.next_uname_id,
+                author_id: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+                uname: uname.clone(),
+            })?;
+            self.next_uname_id += 1;
+        }
+        // Notes
+        for note in person.notes.iter() {
+            self.writer.author_notes.write(AuthorNoteRow {
+                id: self.next_author_note_id,
+                author_id: *self.author_map.get(&(person.name.clone(), person.id)).unwrap(),
+                r#type: note.0.clone(),
+                note: note.1.clone(),
+            })?;
+            self.next_author_note_id += 1;
+        }
         // Alias
         for alias in person.alias.iter() {
-            self.author_map.insert((alias.0.clone(), alias.1.clone()), self.next_author_id);
-            self.writer.write_aliases((
-                self.next_alias_id,
-                self.next_author_id,
-                alias.0.clone(),
-                alias.1.clone()
-                ));
+            self.author_map.insert((alias.0.clone(), alias.1), key);
+            self.writer.aliases.write(AliasRow {
+                id: self.next_alias_id,
+                author_id: key,
+                alias: alias.0.clone(),
+                alias_id: alias.1,
+            })?;
             self.next_alias_id += 1;
         }
-        self.next_author_id += 1;
+        Ok(())
+    }
+
+    fn read_contributor(&mut self, start: &BytesStart) -> Result<Contributor, RejectReason> {
+        let orcid = self.read_attribute(start, "orcid")?;
+        let raw_name = self.read_text(start)?;
+        let (name, id) = read_person_name(&raw_name);
+        Ok(Contributor { raw_name, name, id, orcid })
+    }
+
+    fn read_attribute(&self, start: &BytesStart, name: &str) -> Result<Option<String>, RejectReason> {
+        match start.try_get_attribute(name)? {
+            Some(attr) => Ok(Some(attr.decode_and_unescape_value(self.reader.decoder())?.into_owned())),
+            None => Ok(None),
+        }
     }
 
-    fn read_text(&mut self, start: &BytesStart) -> Result<String, Box<dyn Error>> {
+    fn read_text(&mut self, start: &BytesStart) -> Result<String, RejectReason> {
         let mut buf = Vec::new();
         let mut text = String::new();
         loop {
@@ -431,48 +713,161 @@ fn read_person(&mut self, eve: &BytesStart) -> Result<(), Box<dyn Error>> {
                 }
                 Ok(Event::Start(e)) => match e.name().as_ref() {
                     b"i" | b"ref" | b"sup" | b"sub" | b"tt" => {},
-                    _ => return Err(format!("Unexpected event start {0}", String::from_utf8_lossy(e.name().as_ref())).into())
+                    _ => return Err(RejectReason::UnexpectedElement(String::from_utf8_lossy(e.name().as_ref()).into_owned()))
                 },
                 Ok(Event::End(e)) if e.name().as_ref() == start.name().as_ref() => break,
                 Ok(Event::End(e)) => match e.name().as_ref() {
                     b"i" | b"ref" | b"sup" | b"sub" | b"tt" => {},
-                    _ => return Err(format!("Unexpected end event {0}", String::from_utf8_lossy(e.name().as_ref())).into()),
+                    _ => return Err(RejectReason::UnexpectedElement(String::from_utf8_lossy(e.name().as_ref()).into_owned())),
                 },
-                _ => return Err(format!("Unexpected event {0} {1}", self.reader.buffer_position(), self.reader.error_position()).into()),
+                Ok(Event::Eof) => return Err(RejectReason::UnexpectedEof),
+                Err(e) => return Err(e.into()),
+                Ok(_) => return Err(RejectReason::UnexpectedContent(String::from_utf8_lossy(start.name().as_ref()).into_owned())),
             }
         }
         Ok(text)
     }
     
-    fn read_int(&mut self, start: &BytesStart) -> Result<usize, Box<dyn Error>> {
-        let value =  self.read_text(start)?;
-        Ok(value.parse::<usize>().unwrap_or_else(|e1| { 
-            let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
-            panic!("key: {name} value:{value} {e1}")
-        }))
+    fn read_int(&mut self, start: &BytesStart) -> Result<usize, RejectReason> {
+        let value = self.read_text(start)?;
+        value.parse::<usize>().map_err(|_| RejectReason::InvalidNumber {
+            field: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            value,
+        })
+    }
+}
+
+/// Why a DBLP record was rejected.
+#[derive(Debug)]
+pub enum RejectReason {
+    MissingAttribute(&'static str),
+    MissingField(&'static str),
+    InvalidNumber { field: String, value: String },
+    InvalidDate(InvalidDate),
+    UnexpectedElement(String),
+    UnexpectedContent(String),
+    UnexpectedEof,
+    Xml(quick_xml::Error),
+}
+
+impl RejectReason {
+    /// Short name used to count rejects per reason.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectReason::MissingAttribute(_) => "missing_attribute",
+            RejectReason::MissingField(_) => "missing_field",
+            RejectReason::InvalidNumber { .. } => "invalid_number",
+            RejectReason::InvalidDate(_) => "invalid_date",
+            RejectReason::UnexpectedElement(_) => "unexpected_element",
+            RejectReason::UnexpectedContent(_) => "unexpected_content",
+            RejectReason::UnexpectedEof => "unexpected_eof",
+            RejectReason::Xml(_) => "xml",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::MissingAttribute(name) => write!(f, "missing attribute {name}"),
+            RejectReason::MissingField(name) => write!(f, "missing field {name}"),
+            RejectReason::InvalidNumber { field, value } => write!(f, "invalid number in {field}: {value}"),
+            RejectReason::InvalidDate(e) => write!(f, "{e}"),
+            RejectReason::UnexpectedElement(name) => write!(f, "unexpected element {name}"),
+            RejectReason::UnexpectedContent(name) => write!(f, "unexpected content in {name}"),
+            RejectReason::UnexpectedEof => write!(f, "unexpected end of file"),
+            RejectReason::Xml(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<quick_xml::Error> for RejectReason {
+    fn from(e: quick_xml::Error) -> Self {
+        RejectReason::Xml(e)
+    }
+}
+
+impl From<InvalidDate> for RejectReason {
+    fn from(e: InvalidDate) -> Self {
+        RejectReason::InvalidDate(e)
+    }
+}
+
+impl From<AttrError> for RejectReason {
+    fn from(e: AttrError) -> Self {
+        RejectReason::Xml(e.into())
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(std::io::Error),
+    /// The xml itself is malformed outside of a record.
+    Xml { offset: u64, source: quick_xml::Error },
+    /// The reader stopped making progress after an error in lenient mode.
+    Stuck { offset: u64 },
+    /// A record was rejected in strict mode.
+    Rejected { key: Option<String>, reason: RejectReason, offset: u64 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "{e}"),
+            ParseError::Xml { offset, source } => write!(f, "malformed xml at byte {offset}: {source}"),
+            ParseError::Stuck { offset } => write!(f, "unable to continue parsing at byte {offset}"),
+            ParseError::Rejected { key, reason, offset } => write!(
+                f,
+                "rejected record {} at byte {offset}: {reason}",
+                key.as_deref().unwrap_or("<unknown>")
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            ParseError::Xml { source, .. } => Some(source),
+            ParseError::Rejected { reason: RejectReason::Xml(e), .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ParseError {
+    fn from(e: csv::Error) -> Self {
+        ParseError::Io(e.into())
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Publication {
-    pubtype: String,
-    key: String,
-    mdate: String,
-    title: String,
-    year: Option<usize>,
-    month: Option<String>,
-    pages: Option<String>,
-    volume: Option<String>,
-    number: Option<String>,
-    journal: Option<String>,
-    publisher: Option<String>,
-    book_title: Option<String>,
-    school: Option<String>,
-    isbn: Option<String>,
-    editor: Vec<String>,
-    references: Vec<(String, String)>, // cite, crossref
-    resources: Vec<(String, String)>,  // ee, url, note (without isbn tagged notes), series, stream
-    authors: Vec<Person>,
+    pub(crate) pubtype: String,
+    pub(crate) key: String,
+    pub(crate) mdate: String,
+    pub(crate) title: String,
+    pub(crate) year: Option<usize>,
+    pub(crate) month: Option<String>,
+    pub(crate) pages: Option<String>,
+    pub(crate) volume: Option<String>,
+    pub(crate) number: Option<String>,
+    pub(crate) journal: Option<String>,
+    pub(crate) publisher: Option<String>,
+    pub(crate) book_title: Option<String>,
+    pub(crate) school: Option<String>,
+    pub(crate) isbn: Option<String>,
+    pub(crate) editor: Vec<Contributor>,
+    pub(crate) references: Vec<(String, String)>, // cite, crossref
+    pub(crate) resources: Vec<(String, String)>,  // ee, url, note (without isbn tagged notes), series, stream
+    pub(crate) authors: Vec<Contributor>,
 }
 
 impl Publication {
@@ -499,8 +894,21 @@ impl Publication {
         }
     }
 
-    pub fn fulfills_constraints(&self) -> bool {
-        !self.pubtype.is_empty() && !self.key.is_empty() && !self.mdate.is_empty() && !self.title.is_empty()
+    pub fn check_constraints(&self) -> Result<(), RejectReason> {
+        if self.pubtype.is_empty() {
+            return Err(RejectReason::MissingField("type"));
+        }
+        if self.key.is_empty() {
+            return Err(RejectReason::MissingAttribute("key"));
+        }
+        if self.mdate.is_empty() {
+            return Err(RejectReason::MissingAttribute("mdate"));
+        }
+        epoch_days(&self.mdate)?;
+        if self.title.is_empty() {
+            return Err(RejectReason::MissingField("title"));
+        }
+        Ok(())
     }
 }
 
@@ -529,25 +937,46 @@ impl fmt::Debug for Publication {
     }
 }
 
+/// An author or editor as listed on a publication, in document order.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Contributor {
+    pub(crate) raw_name: String,
+    pub(crate) name: String,
+    pub(crate) id: usize,
+    pub(crate) orcid: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct Person {
-    name: String,
-    id: usize,
-    alias: Vec<(String, usize)>,
-    mdate: String,
-    affiliations: Vec<(String, String)>,
-    urls: Vec<String>,
+    pub(crate) key: String,
+    pub(crate) name: String,
+    pub(crate) id: usize,
+    pub(crate) alias: Vec<(String, usize)>,
+    pub(crate) mdate: String,
+    pub(crate) affiliations: Vec<(String, String)>, // affiliation, label (current, former)
+    pub(crate) awards: Vec<(String, Option<String>)>, // award, label
+    pub(crate) unames: Vec<String>,
+    pub(crate) notes: Vec<(String, String)>, // type, note (disambiguation, isnot, ...)
+    pub(crate) urls: Vec<String>,
+    pub(crate) identifiers: Vec<(String, String)>, // scheme, identifier
+    pub(crate) orcid: Option<String>,
 }
 
 impl Person {
     fn new() -> Person {
         Person {
+            key: String::new(),
             name: String::new(),
             id: 0,
             alias: Vec::new(),
             mdate: String::new(),
             affiliations: Vec::new(),
+            awards: Vec::new(),
+            unames: Vec::new(),
+            notes: Vec::new(),
             urls: Vec::new(),
+            identifiers: Vec::new(),
+            orcid: None,
         }
     }
 
@@ -555,472 +984,132 @@ impl Person {
         (self.name, self.id) = read_person_name(name);
     }
 
-    fn check_valid(&self) -> bool {
+    fn check_valid(&self) -> Result<(), RejectReason> {
         if self.name.is_empty() {
-            return false
+            return Err(RejectReason::MissingField("author"));
         }
-        true
+        epoch_days(&self.mdate)?;
+        Ok(())
     }
 }
 
 impl fmt::Debug for Person {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Person")
+            .field("key", &self.key)
             .field("name", &self.name)
             .field("id", &self.id)
             .field("alias", &self.alias)
             .field("mdate", &self.mdate)
             .field("affiliations", &self.affiliations)
+            .field("awards", &self.awards)
+            .field("unames", &self.unames)
+            .field("notes", &self.notes)
             .field("urls", &self.urls)
+            .field("identifiers", &self.identifiers)
+            .field("orcid", &self.orcid)
             .finish()
     }
 }
 
 struct WriteManager {
-    venues: Vec<(usize, Option<String>, Option<String>)>,
-    publishers: Vec<(usize, Option<String>)>,
-    editors: Vec<(usize, String)>,
-    authors: Vec<(usize, String, usize, String)>,
-    publications: Vec<(String, String, String, Option<usize>, Option<String>, String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>, Option<usize>, Option<usize>)>,
-    resources: Vec<(usize, String, String, String)>,
-    publication_editors: Vec<(String, usize)>,
-    references: Vec<(String, String, String)>,
-    publication_authors: Vec<(String, usize)>,
-    author_websites: Vec<(usize, usize, String)>,
-    affiliations: Vec<(usize, usize, String, String)>,
-    aliases: Vec<(usize, usize, String, usize)>,
+    venues: TableWriter<VenueRow>,
+    publishers: TableWriter<PublisherRow>,
+    editors: TableWriter<EditorRow>,
+    authors: TableWriter<AuthorRow>,
+    publications: TableWriter<PublicationRow>,
+    resources: TableWriter<ResourceRow>,
+    publication_editors: TableWriter<PublicationEditorRow>,
+    references: TableWriter<ReferenceRow>,
+    publication_authors: TableWriter<PublicationAuthorRow>,
+    author_websites: TableWriter<AuthorWebsiteRow>,
+    affiliations: TableWriter<AffiliationRow>,
+    aliases: TableWriter<AliasRow>,
+    awards: TableWriter<AwardRow>,
+    unames: TableWriter<UnameRow>,
+    author_notes: TableWriter<AuthorNoteRow>,
+    author_identifiers: TableWriter<AuthorIdentifierRow>,
+    rejects: TableWriter<RejectRow>,
 }
 
 impl WriteManager {
-    
-    pub fn new() -> WriteManager {
-        // Touch all csv files and add header
-        touch_file(VENUE_FILE, &["id", "name", "type"]);
-        touch_file(PUBLISHER_FILE, &["id", "name"]);
-        touch_file(EDITOR_FILE, &["id", "name"]);
-        touch_file(AUTHOR_FILE, &["key", "id", "name", "mdate"]);
-        touch_file(PUBLICATION_FILE,
-                   &["key",
-                       "mdate",
-                       "title",
-                       "year",
-                       "month",
-                       "type",
-                       "school",
-                       "isbn",
-                       "pages",
-                       "volume",
-                       "number",
-                       "venue_id",
-                       "publisher_id"]);
-        touch_file(RESOURCES_FILE, &["id", "type", "value", "publication_key"]);
-        touch_file(PUBLICATION_EDITOR_FILE, &["publication_key", "editor_id"]);
-        touch_file(REFERENCE_FILE, &["type", "origin_pub", "dest_pub"]);
-        touch_file(PUBLICATION_AUTHORS_FILE, &["publication_key", "author_id"]);
-        touch_file(AUTHOR_WEBSITES_FILE, &["id", "author_id", "url"]);
-        touch_file(AFFILIATIONS_FILE, &["id", "author_id", "affiliation", "type"]);
-        touch_file(ALIAS_FILE, &["id", "author_id", "alias", "alias_id"]);
-        WriteManager {
-            venues: vec![],
-            publishers: vec![],
-            editors: vec![],
-            authors: vec![],
-            publications: vec![],
-            resources: vec![],
-            publication_editors: vec![],
-            references: vec![],
-            publication_authors: vec![],
-            author_websites: vec![],
-            affiliations: vec![],
-            aliases: vec![],
-        }
+    pub fn new(dir: &str, format: OutputFormat) -> io::Result<WriteManager> {
+        // Keep the schema next to the tsv files it describes
+        fs::write(file_in(dir, SCHEMA_FILE), create_tables_dblp_sql(true, true))?;
+        Ok(WriteManager {
+            venues: TableWriter::new(dir, format)?,
+            publishers: TableWriter::new(dir, format)?,
+            editors: TableWriter::new(dir, format)?,
+            authors: TableWriter::new(dir, format)?,
+            publications: TableWriter::new(dir, format)?,
+            resources: TableWriter::new(dir, format)?,
+            publication_editors: TableWriter::new(dir, format)?,
+            references: TableWriter::new(dir, format)?,
+            publication_authors: TableWriter::new(dir, format)?,
+            author_websites: TableWriter::new(dir, format)?,
+            affiliations: TableWriter::new(dir, format)?,
+            aliases: TableWriter::new(dir, format)?,
+            awards: TableWriter::new(dir, format)?,
+            unames: TableWriter::new(dir, format)?,
+            author_notes: TableWriter::new(dir, format)?,
+            author_identifiers: TableWriter::new(dir, format)?,
+            // The reject log is meant to be read, so it stays tsv
+            rejects: TableWriter::new(dir, OutputFormat::Tsv)?,
+        })
     }
 
-    pub fn write_venue(&mut self, tuple: (usize, Option<String>, Option<String>)) {
-        self.venues.push(tuple);
-        if self.venues.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(VENUE_FILE)
-                    .unwrap());
-            for tuple in &self.venues {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.venues.clear()
-        }
-    }
-    pub fn write_publisher(&mut self, tuple: (usize, Option<String>)) {
-        self.publishers.push(tuple);
-        if self.publishers.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLISHER_FILE)
-                    .unwrap());
-            for tuple in &self.publishers {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publishers.clear()
-        }
-    }
-    pub fn write_editor(&mut self, tuple: (usize, String)) {
-        self.editors.push(tuple);
-        if self.editors.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(EDITOR_FILE)
-                    .unwrap());
-            for tuple in &self.editors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.editors.clear()
-        }
-    }
-    pub fn write_author(&mut self, tuple: (usize, String, usize, String)) {
-        self.authors.push(tuple);
-        if self.authors.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AUTHOR_FILE)
-                    .unwrap());
-            for tuple in &self.authors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.authors.clear()
-        }
-    }
-    pub fn write_publication(&mut self, tuple: (String, String, String, Option<usize>, Option<String>, String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>, Option<usize>, Option<usize>)) {
-        self.publications.push(tuple);
-        if self.publications.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_FILE)
-                    .unwrap());
-            for tuple in &self.publications {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publications.clear()
-        }
-    }
-    pub fn write_resource(&mut self, tuple: (usize, String, String, String)) {
-        self.resources.push(tuple);
-        if self.resources.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(RESOURCES_FILE)
-                    .unwrap());
-            for tuple in &self.resources {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.resources.clear()
-        }
-    }
-    pub fn write_publication_editor(&mut self, tuple: (String, usize)) {
-        self.publication_editors.push(tuple);
-        if self.publication_editors.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_EDITOR_FILE)
-                    .unwrap());
-            for tuple in &self.publication_editors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publication_editors.clear()
-        }
-    }
-    pub fn write_reference(&mut self, tuple: (String, String, String)) {
-        self.references.push(tuple);
-        if self.references.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(REFERENCE_FILE)
-                    .unwrap());
-            for tuple in &self.references {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.references.clear()
-        }
-    }
-    pub fn write_publication_author(&mut self, tuple: (String, usize)) {
-        self.publication_authors.push(tuple);
-        if self.publication_authors.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_AUTHORS_FILE)
-                    .unwrap());
-            for tuple in &self.publication_authors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publication_authors.clear()
-        }
-    }
-    pub fn write_author_website(&mut self, tuple: (usize, usize, String)) {
-        self.author_websites.push(tuple);
-        if self.author_websites.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AUTHOR_WEBSITES_FILE)
-                    .unwrap());
-            for tuple in &self.author_websites {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.author_websites.clear()
-        }
-    }
-    pub fn write_affiliation(&mut self, tuple: (usize, usize, String, String)) {
-        self.affiliations.push(tuple);
-        if self.affiliations.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AFFILIATIONS_FILE)
-                    .unwrap());
-            for tuple in &self.affiliations {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.affiliations.clear()
-        }
-    }
-    pub fn write_aliases(&mut self, tuple: (usize, usize, String, usize)) {
-        self.aliases.push(tuple);
-        if self.aliases.len() == 10000 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(ALIAS_FILE)
-                    .unwrap());
-            for tuple in &self.aliases {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.aliases.clear()
-        }
+    pub fn finalize(&mut self) -> io::Result<()> {
+        self.venues.flush()?;
+        self.publishers.flush()?;
+        self.editors.flush()?;
+        self.authors.flush()?;
+        self.publications.flush()?;
+        self.resources.flush()?;
+        self.publication_editors.flush()?;
+        self.references.flush()?;
+        self.publication_authors.flush()?;
+        self.author_websites.flush()?;
+        self.affiliations.flush()?;
+        self.aliases.flush()?;
+        self.awards.flush()?;
+        self.unames.flush()?;
+        self.author_notes.flush()?;
+        self.author_identifiers.flush()?;
+        self.rejects.flush()?;
+        Ok(())
     }
-    
-    pub fn finalize(&mut self) {
-        if self.venues.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(VENUE_FILE)
-                    .unwrap());
-            for tuple in &self.venues {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.venues.clear()
-        }
-        if self.publishers.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLISHER_FILE)
-                    .unwrap());
-            for tuple in &self.publishers {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publishers.clear()
-        }
-        if self.editors.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(EDITOR_FILE)
-                    .unwrap());
-            for tuple in &self.editors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.editors.clear()
-        }
-        if self.affiliations.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AFFILIATIONS_FILE)
-                    .unwrap());
-            for tuple in &self.affiliations {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.affiliations.clear()
-        }
-        if self.authors.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AUTHOR_FILE)
-                    .unwrap());
-            for tuple in &self.authors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.authors.clear()
-        }
-        if self.publications.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_FILE)
-                    .unwrap());
-            for tuple in &self.publications {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publications.clear()
-        }
-        if self.resources.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(RESOURCES_FILE)
-                    .unwrap());
-            for tuple in &self.resources {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.resources.clear()
-        }
-        if self.publication_editors.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_EDITOR_FILE)
-                    .unwrap());
-            for tuple in &self.publication_editors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publication_editors.clear()
-        }
-        if self.references.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(REFERENCE_FILE)
-                    .unwrap());
-            for tuple in &self.references {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.references.clear()
-        }
-        if self.publication_authors.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(PUBLICATION_AUTHORS_FILE)
-                    .unwrap());
-            for tuple in &self.publication_authors {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.publication_authors.clear()
-        }
-        if self.author_websites.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(AUTHOR_WEBSITES_FILE)
-                    .unwrap());
-            for tuple in &self.author_websites {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.author_websites.clear()
-        }
-        if self.aliases.len() > 0 {
-            let mut wrt = WriterBuilder::new()
-                .delimiter(b'\t')
-                .from_writer(OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(ALIAS_FILE)
-                    .unwrap());
-            for tuple in &self.aliases {
-                wrt.serialize(tuple).unwrap();
-            }
-            wrt.flush().unwrap();
-            self.aliases.clear()
-        }
+
+    /// Completes all files after the last row was written.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.venues.finish()?;
+        self.publishers.finish()?;
+        self.editors.finish()?;
+        self.authors.finish()?;
+        self.publications.finish()?;
+        self.resources.finish()?;
+        self.publication_editors.finish()?;
+        self.references.finish()?;
+        self.publication_authors.finish()?;
+        self.author_websites.finish()?;
+        self.affiliations.finish()?;
+        self.aliases.finish()?;
+        self.awards.finish()?;
+        self.unames.finish()?;
+        self.author_notes.finish()?;
+        self.author_identifiers.finish()?;
+        self.rejects.finish()?;
+        Ok(())
     }
 }
 
-fn touch_file<I, T>(file: &str, record: I) -> ()
-where
-    I: IntoIterator<Item=T>,
-    T: AsRef<[u8]>,
-{
-    let mut wrt = WriterBuilder::new().delimiter(b'\t').from_path(file).unwrap();
-    wrt.write_record(record).unwrap();
-    wrt.flush().unwrap();
+fn record_key(start: &BytesStart) -> Option<String> {
+    let attr = start.try_get_attribute("key").ok()??;
+    Some(String::from_utf8_lossy(attr.value.as_ref()).into_owned())
+}
+
+/// First id after the given ones, continuing a previous parse.
+fn next_id(ids: impl Iterator<Item = usize>) -> usize {
+    ids.map(|id| id + 1).max().unwrap_or(0)
 }
 
 fn read_person_name(name: &str) -> (String, usize) {
@@ -1034,4 +1123,148 @@ fn read_person_name(name: &str) -> (String, usize) {
         let name = name.to_string();
         (name, 0)
     }
-}
\ No newline at end of file
+}
+pub(crate) const DBLP_SCHEMA: &str = "https://dblp.org/rdf/schema#";
+
+/// Identifier schemes recognized in person urls: (scheme, url pattern capturing the identifier).
+static IDENTIFIER_PATTERNS: LazyLock<Vec<(&str, Regex)>> = LazyLock::new(|| {
+    [
+        ("orcid", r"orcid\.org/(\d{4}-\d{4}-\d{4}-\d{3}[\dX])"),
+        ("wikidata", r"wikidata\.org/(?:wiki|entity)/(Q\d+)"),
+        ("wikipedia", r"wikipedia\.org/wiki/([^/?#]+)"),
+        ("google-scholar", r"scholar\.google\.[a-z.]+/citations\?(?:.*&)?user=([\w-]+)"),
+        ("researchgate", r"researchgate\.net/profile/([^/?#]+)"),
+        ("acm", r"dl\.acm\.org/profile/(\d+)"),
+        ("scopus", r"scopus\.com/authid/detail\.uri\?(?:.*&)?authorId=(\d+)"),
+        ("mathgenealogy", r"genealogy\.(?:math\.ndsu\.nodak\.edu|ams\.org)/id\.php\?id=(\d+)"),
+        ("zbmath", r"zbmath\.org/authors/(?:\?q=ai:)?([\w.\-]+)"),
+        ("loc", r"id\.loc\.gov/authorities/names/(\w+)"),
+        ("viaf", r"viaf\.org/viaf/(\d+)"),
+        ("gnd", r"d-nb\.info/gnd/([\dX-]+)"),
+        ("isni", r"isni\.org/isni/(\d{15}[\dX])"),
+        ("twitter", r"(?:twitter|x)\.com/(\w+)"),
+        ("github", r"github\.com/([\w-]+)"),
+    ]
+    .into_iter()
+    // The host has to start at the scheme, a dot or the start of the url, so x.com doesn't match netflix.com
+    .map(|(scheme, pattern)| (scheme, Regex::new(&format!(r"(?:^|//|\.){pattern}")).unwrap()))
+    .collect()
+});
+
+/// Extracts the identifier scheme and value from a person url.
+pub(crate) fn read_identifier(url: &str) -> Option<(String, String)> {
+    IDENTIFIER_PATTERNS.iter().find_map(|(scheme, re)| {
+        re.captures(url)
+            .map(|caps| (scheme.to_string(), caps.get(1).unwrap().as_str().to_string()))
+    })
+}
+
+/// RDF predicate the dblp knowledge graph uses to link a person to an identifier of the scheme.
+pub(crate) fn identifier_predicate(scheme: &str) -> String {
+    let local = match scheme {
+        "orcid" => "orcid",
+        "wikidata" => "wikidata",
+        "wikipedia" => "wikipedia",
+        _ => "webpage",
+    };
+    format!("{DBLP_SCHEMA}{local}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fixture, scratch_dir};
+
+    #[test]
+    fn reads_identifiers_of_known_hosts() {
+        let identifier = |url| read_identifier(url).map(|(scheme, value)| format!("{scheme}:{value}"));
+        assert_eq!(identifier("https://orcid.org/0000-0002-1825-0097").as_deref(), Some("orcid:0000-0002-1825-0097"));
+        assert_eq!(identifier("https://twitter.com/dblp_org").as_deref(), Some("twitter:dblp_org"));
+        assert_eq!(identifier("https://x.com/dblp_org").as_deref(), Some("twitter:dblp_org"));
+        assert_eq!(identifier("https://mobile.twitter.com/dblp_org").as_deref(), Some("twitter:dblp_org"));
+        assert_eq!(identifier("x.com/dblp_org").as_deref(), Some("twitter:dblp_org"));
+        assert_eq!(identifier("https://en.wikipedia.org/wiki/Alan_Turing").as_deref(), Some("wikipedia:Alan_Turing"));
+    }
+
+    #[test]
+    fn ignores_hosts_ending_in_a_known_host() {
+        assert_eq!(read_identifier("https://www.dropbox.com/s/abc"), None);
+        assert_eq!(read_identifier("https://www.netflix.com/title"), None);
+        assert_eq!(read_identifier("https://notgithub.com/user"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        let dir = scratch_dir("parser_dates");
+        let file = format!("{dir}/dblp.xml");
+        fs::write(
+            &file,
+            r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<dblp>
+<article mdate="2020-02-30" key="journals/x/Bad20"><title>Bad.</title><year>2020</year></article>
+<article mdate="2020-02-29" key="journals/x/Good20"><title>Good.</title><year>2020</year></article>
+</dblp>
+"#,
+        )
+        .unwrap();
+        let mut parser = Parser::new(&file, &dir, ParseMode::Lenient, OutputFormat::Tsv).unwrap();
+        let stats = parser.run().unwrap();
+        assert_eq!(stats.rejects.get("invalid_date"), Some(&1));
+        let rejects = read_rows::<RejectRow>(&dir).unwrap();
+        assert_eq!(rejects[0].key.as_deref(), Some("journals/x/Bad20"));
+        let publications = read_rows::<PublicationRow>(&dir).unwrap();
+        assert_eq!(publications.len(), 1);
+        assert_eq!(publications[0].key, "journals/x/Good20");
+    }
+
+    #[test]
+    fn logs_rejected_records() {
+        let file = fixture("dblp.rejects.xml");
+        let content = fs::read_to_string(&file).unwrap();
+        // The reader is at the line break before a record when it starts reading it
+        let offset = |record: &str| content.find(&format!("\n{record}")).unwrap() as u64;
+        let dir = scratch_dir("parser_rejects");
+        let mut parser = Parser::new(&file, &dir, ParseMode::Lenient, OutputFormat::Tsv).unwrap();
+        let stats = parser.run().unwrap();
+
+        assert_eq!(
+            stats.rejects,
+            BTreeMap::from([("invalid_date", 1), ("invalid_number", 1), ("missing_attribute", 1)])
+        );
+        let rejects: Vec<(Option<String>, String, String, u64)> = read_rows::<RejectRow>(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.key, row.reason, row.detail, row.offset))
+            .collect();
+        assert_eq!(
+            rejects,
+            vec![
+                (None, "missing_attribute".into(), "missing attribute key".into(), offset("<article mdate=\"2020-01-01\">")),
+                (
+                    Some("journals/x/Year20".into()),
+                    "invalid_number".into(),
+                    "invalid number in year: 20x0".into(),
+                    offset("<article mdate=\"2020-01-01\" key=\"journals/x/Year20\">"),
+                ),
+                (
+                    Some("journals/x/Date20".into()),
+                    "invalid_date".into(),
+                    "invalid date 2020-13-01".into(),
+                    offset("<article mdate=\"2020-13-01\""),
+                ),
+            ]
+        );
+        let publications = read_rows::<PublicationRow>(&dir).unwrap();
+        assert_eq!(publications.len(), 1);
+        assert_eq!(publications[0].key, "journals/x/Good20");
+
+        // Strict mode stops at the first of them
+        let mut parser = Parser::new(&file, &scratch_dir("parser_rejects_strict"), ParseMode::Strict, OutputFormat::Tsv).unwrap();
+        match parser.run() {
+            Err(ParseError::Rejected { key: None, reason: RejectReason::MissingAttribute("key"), offset: at }) => {
+                assert_eq!(at, offset("<article mdate=\"2020-01-01\">"))
+            }
+            other => panic!("expected the record without a key to be rejected, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::File;
+
+/// Plan an engine executed a query with, as the engine reports it, and the figures of it that are compared
+/// across engines and runs.
+pub struct QueryPlan {
+    pub plan: Value,
+    /// Columns of the plan summary, as (name, value). Every plan of an engine has the same columns.
+    pub summary: Vec<(&'static str, String)>,
+}
+
+/// Writes the plans of a run next to its results: `{name}.plans.json` with the full plans and
+/// `{name}.plans.tsv` with one summary line per query.
+pub fn write_plans(plans: &[(usize, &str, QueryPlan)], name: &str) -> Result<(), Box<dyn Error>> {
+    let full: Vec<Value> = plans
+        .iter()
+        .map(|(id, query, plan)| json!({ "id": id, "name": query, "plan": plan.plan }))
+        .collect();
+    serde_json::to_writer_pretty(File::create(format!("{name}.plans.json"))?, &full)?;
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_writer(File::create(format!("{name}.plans.tsv"))?);
+    if let Some((_, _, first)) = plans.first() {
+        let mut header = vec!["id", "name"];
+        header.extend(first.summary.iter().map(|(column, _)| *column));
+        writer.write_record(header)?;
+    }
+    for (id, query, plan) in plans {
+        let mut record = vec![id.to_string(), query.to_string()];
+        record.extend(plan.summary.iter().map(|(_, value)| value.clone()));
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Calls `visit` for the node and all nodes below it, where `children` names the field of the child nodes.
+pub fn visit_nodes<'a>(node: &'a Value, children: &str, visit: &mut impl FnMut(&'a Value)) {
+    visit(node);
+    if let Some(nodes) = node.get(children).and_then(Value::as_array) {
+        for child in nodes {
+            visit_nodes(child, children, visit);
+        }
+    }
+}
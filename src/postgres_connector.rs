@@ -1,245 +1,830 @@
-use crate::parser::Parser;
-use crate::{AFFILIATIONS_FILE, ALIAS_FILE, AUTHOR_FILE, AUTHOR_WEBSITES_FILE, EDITOR_FILE, PUBLICATION_AUTHORS_FILE, PUBLICATION_EDITOR_FILE, PUBLICATION_FILE, PUBLISHER_FILE, REFERENCE_FILE, RESOURCES_FILE, VENUE_FILE};
-use bollard::models::{ContainerCreateBody, HostConfig, PortBinding};
+use crate::containers;
+use crate::formats::OutputFormat;
+use crate::osm::{read_pbf, OsmLoader, RAW_OSM_COLUMNS, RAW_OSM_SQL};
+use crate::plans::{visit_nodes, QueryPlan};
+use crate::incremental::{change_file, delete_sql, staging_sql, update_sql, ChangeKind};
+use crate::schemas::{drop_sql, Deferred, SchemaVariant};
+use crate::tables::{dblp_tables, file_in, foreign_keys_sql, keys_sql, TableInfo};
+use crate::{RunConfig, SetupRecord, CHANGES_DIR, DATA_DIR, PREVIOUS_DIR};
+use bollard::models::{ContainerCreateBody, HealthConfig, HostConfig, PortBinding};
 use bollard::query_parameters::CreateContainerOptionsBuilder;
 use bollard::Docker;
 use futures::TryStreamExt;
-use postgres::types::ToSql;
-use postgres::{Client, NoTls, Row};
+use postgres::config::Host;
+use native_tls::TlsConnector;
+use postgres::{Client, Config, Row, SimpleQueryMessage, Transaction};
+use postgres_native_tls::MakeTlsConnector;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Read, Write};
-use std::thread::sleep;
+use std::env;
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use std::process::Command;
 
+/// How osm2pgsql imports an OSM extract, the defaults are those of osm2pgsql itself.
+#[derive(Debug)]
+pub struct Osm2pgsqlOptions {
+    /// Lua style file for the flex output, None for the pgsql output with its default style.
+    pub style: Option<String>,
+    /// Keep the intermediate tables in the database instead of memory.
+    pub slim: bool,
+    /// Node cache size in MB.
+    pub cache: Option<u32>,
+    /// Number of threads.
+    pub processes: Option<u16>,
+    /// Put the tags without a column of their own into an hstore column, pgsql output only.
+    pub hstore: bool,
+}
+
+impl Osm2pgsqlOptions {
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(style) = &self.style {
+            args.extend(["--output=flex".to_string(), format!("--style={style}")]);
+        }
+        if self.slim {
+            args.push("--slim".to_string());
+        }
+        if let Some(cache) = self.cache {
+            args.push(format!("--cache={cache}"));
+        }
+        if let Some(processes) = self.processes {
+            args.push(format!("--number-processes={processes}"));
+        }
+        if self.hstore {
+            args.push("--hstore".to_string());
+        }
+        args
+    }
+}
+
+/// Size of the chunks table files are sent to COPY in.
+const COPY_BUFFER_SIZE: usize = 1 << 20;
+
+/// How long a new container may take to initialize its database and accept connections.
+const READY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Settings recorded with the results of every run, next to the ones set with --postgres-setting.
+const RECORDED_SETTINGS: [&str; 7] = [
+    "shared_buffers",
+    "work_mem",
+    "maintenance_work_mem",
+    "effective_cache_size",
+    "max_parallel_workers_per_gather",
+    "random_page_cost",
+    "jit",
+];
+
+/// Extensions a dataset needs, by the first word of its name, and the ones the selected setup statements declare.
+/// They are installed before the data is loaded, so the setup statements and queries can use them too.
+fn dataset_extensions<'a>(kind: &str, config: &'a RunConfig) -> Vec<&'a str> {
+    let mut extensions = match kind {
+        // Only the raw osm table and osm2pgsql's --hstore keep tags in an hstore column
+        "osm-country" if config.osm_loader == OsmLoader::Raw || config.osm2pgsql.hstore => vec!["postgis", "hstore"],
+        "osm-country" => vec!["postgis"],
+        _ => Vec::new(),
+    };
+    let setup = config.setup.iter().filter(|record| !record.postgresql.is_empty());
+    for extension in setup.flat_map(|record| record.extensions.split(',')).map(str::trim) {
+        if !extension.is_empty() && !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    }
+    extensions
+}
+
 pub struct PostgresConnection {
     client: Client,
     dataset: String,
-    docker_id: String,
+    /// Connection string of the server, also handed to osm2pgsql.
+    conn_str: String,
+    /// Container and image of the server, None for a server given with --postgres-url.
+    docker_id: Option<String>,
+    image: Option<String>,
+    /// Leave the container and its data running after the run.
+    keep_container: bool,
 }
 
 impl PostgresConnection {
 
-    pub fn new(dataset: &String) -> Result<Self, Box<dyn Error>> {
-        // Startup Docker container
-        let rt = Runtime::new()?;
-        let handle = rt.handle();
-        let id = handle.block_on(async {
-            let docker = Docker::connect_with_defaults().unwrap();
-            let mut image = "postgres:latest";
-
-            if dataset.contains("osm") {
-                image = "postgis/postgis:18-3.6";
+    pub fn new(dataset: &str, config: &RunConfig) -> Result<Self, Box<dyn Error>> {
+        let (conn_str, docker_id, image) = match &config.postgres_url {
+            Some(url) => (url.clone(), None, None),
+            None => {
+                let image = match &config.postgres_image {
+                    Some(image) => image.clone(),
+                    None if dataset.contains("osm") => String::from("postgis/postgis:18-3.6"),
+                    None => String::from("postgres:latest"),
+                };
+                let (id, port) = start_container(&image, config)?;
+                (container_conn_str(port), Some(id), Some(image))
             }
+        };
 
-            docker.create_image(
-                Some(
-                    bollard::query_parameters::CreateImageOptionsBuilder::default()
-                        .from_image(image)
-                        .build(),
-                ),
-                None,
-                None,
-            )
-                .try_collect::<Vec<_>>()
-                .await.expect("Failed Creating Docker Image");
-
-            let mut port_bindings = HashMap::new();
-            port_bindings.insert(
-                "5432/tcp".to_string(),
-                Some(vec![PortBinding {
-                    host_ip: Some("0.0.0.0".to_string()),
-                    host_port: Some("5432".to_string()),
-                }]),
-            );
-
-            let config = ContainerCreateBody {
-                image: Some(image.into()),
-                env: Some(vec![
-                    "POSTGRES_PASSWORD=password".to_string(),
-                    "POSTGRES_USER=postgres".to_string(),
-                    "POSTGRES_DB=database".to_string()
-                ]),
-                host_config: Some(HostConfig {
-                    port_bindings: Some(port_bindings),
-                    ..Default::default()
-                }),
-                exposed_ports: Some( {
-                    let mut ports = HashMap::new();
-                    ports.insert("5432/tcp".to_string(),HashMap::new());
-                    ports
-                }),
-                ..Default::default()
-            };
-            
-            let options = CreateContainerOptionsBuilder::default().name("postgres").build();
-            
-            let id = docker.create_container(
-                Some(options),
-                config,
-            )
-                .await
-                .expect("Failed to create Docker Container")
-                .id;
-            docker.start_container(&id, None::<bollard::query_parameters::StartContainerOptions>).await.expect("Failed to start Docker Container");
-            id
-        });
-        
         // Connect to Postgres DB
-        let client = create_client();
-        let mut conn = PostgresConnection { client, dataset: dataset.into(), docker_id: id};
+        let client = create_client(&conn_str)?;
+        let mut conn = PostgresConnection {
+            client,
+            dataset: dataset.into(),
+            conn_str,
+            docker_id,
+            image,
+            keep_container: config.keep_containers,
+        };
+        let kind = dataset.split(" ").collect::<Vec<&str>>()[0];
+        conn.install_extensions(&dataset_extensions(kind, config))?;
         // TODO add more datasets
-        match dataset.split(" ").collect::<Vec<&str>>()[0] {
+        match kind {
             "dblp" => {
-                conn.create_tables_dblp();
-                conn.insert_dblp_data();
+                let deferred = Deferred::of(config);
+                conn.create_tables_dblp(config.schema, deferred)?;
+                let dir = if config.incremental { PREVIOUS_DIR } else { DATA_DIR };
+                conn.insert_dblp_data(dir, config.format, config.load_jobs)?;
+                conn.add_deferred_constraints(deferred, config.load_jobs)?;
+                if config.incremental {
+                    conn.apply_dblp_changes(CHANGES_DIR)?;
+                }
+                conn.transform_dblp(config.schema);
             },
             "osm-country" => {
-                conn.client.execute("CREATE EXTENSION postgis;", &[]);
-                conn.insert_osm_data();
+                match config.osm_loader {
+                    OsmLoader::Osm2pgsql => conn.insert_osm_data(&config.osm2pgsql)?,
+                    OsmLoader::Raw => conn.insert_raw_osm_data()?,
+                }
             }
             _ => { return Err("dataset could not be resolved for postgres Connection".into())}
         }
+        conn.post_load(&config.setup)?;
         println!("Finished creating Postgres client");
         Ok(conn)
     }
 
-    fn insert_osm_data(&self) {
+    /// Installs the extensions unless they already are. Fails naming all extensions the server doesn't provide,
+    /// instead of at the first statement that needs one of them.
+    fn install_extensions(&mut self, extensions: &[&str]) -> Result<(), Box<dyn Error>> {
+        let available: Vec<String> = self
+            .client
+            .query("SELECT name FROM pg_available_extensions;", &[])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let missing: Vec<&str> = extensions
+            .iter()
+            .copied()
+            .filter(|extension| !available.iter().any(|name| name == extension))
+            .collect();
+        if !missing.is_empty() {
+            let server = match &self.image {
+                Some(image) => format!("The image {image}"),
+                None => String::from("The server"),
+            };
+            return Err(format!(
+                "{server} lacks Postgres extensions that {} needs: {}. Use one that provides them with --postgres-image or --postgres-url",
+                self.dataset,
+                missing.join(", ")
+            )
+            .into());
+        }
+        for extension in extensions {
+            self.client
+                .batch_execute(&format!("CREATE EXTENSION IF NOT EXISTS \"{extension}\";"))
+                .map_err(|e| format!("Failed to install the Postgres extension {extension}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Extract of the OSM dataset, downloaded before the connection is created.
+    fn osm_file(&self) -> String {
         let dataset_parts: Vec<&str> = self.dataset.split(" ").collect();
         let country = dataset_parts[2];
-        let file_path = format!("/data/{country}-latest.osm.pbf");
-        let osm2pgsql = Command::new("bash")
-        .args(["-c", format!("osm2pgsql -c -d database -U postgres -H 172.17.0.1 -P 5432 {file_path}").as_str()])
+        format!("/data/{country}-latest.osm.pbf")
+    }
+
+    /// Imports the extract of the dataset with osm2pgsql and logs how long that took.
+    fn insert_osm_data(&self, options: &Osm2pgsqlOptions) -> Result<(), Box<dyn Error>> {
+        let file_path = self.osm_file();
+        let now = Instant::now();
+        // osm2pgsql takes the connection string as database name and reads the password file itself
+        let osm2pgsql = Command::new("osm2pgsql")
+        .args(["-c", "-d", self.conn_str.as_str()])
+        .args(options.args())
+        .arg(&file_path)
         .output()
-        .unwrap();
+        .map_err(|e| format!("Failed to run osm2pgsql: {e}"))?;
+        let duration = now.elapsed().as_millis();
         println!("status: {}", &osm2pgsql.status);
         println!("stdout:\n{}", String::from_utf8_lossy(&osm2pgsql.stdout));
         println!("stderr:\n{}", String::from_utf8_lossy(&osm2pgsql.stderr));
-        println!("Finished creating tables and inserting data for OSM");
+        if !osm2pgsql.status.success() {
+            return Err(format!("osm2pgsql failed with {}", osm2pgsql.status).into());
+        }
+        self.append_log(&format!("osm2pgsql {}\nDuration: {duration}\n", options.args().join(" ")));
+        println!("Finished creating tables and inserting data for OSM in {duration} ms");
+        Ok(())
+    }
+
+    /// Streams the extract of the dataset into the raw OSM table and logs how long that took.
+    fn insert_raw_osm_data(&mut self) -> Result<(), Box<dyn Error>> {
+        let file_path = self.osm_file();
+        let now = Instant::now();
+        let mut transaction = self.client.transaction()?;
+        transaction.batch_execute(RAW_OSM_SQL)?;
+        let sink = transaction.copy_in(&format!("COPY osm ({}) FROM STDIN", RAW_OSM_COLUMNS.join(", ")))?;
+        let mut sink = BufWriter::with_capacity(COPY_BUFFER_SIZE, sink);
+        let mut elements = 0;
+        read_pbf(&file_path, |element| {
+            sink.write_all(element.copy_line().as_bytes())?;
+            elements += 1;
+            Ok(())
+        })?;
+        sink.into_inner().map_err(|e| e.into_error())?.finish()?;
+        transaction.commit()?;
+        let duration = now.elapsed().as_millis();
+        self.append_log(&format!("Loaded raw OSM data\nDuration: {duration}\n"));
+        println!("Loaded {elements} OSM elements into Postgres in {duration} ms");
+        Ok(())
     }
 
-    pub fn create_tables_dblp(&mut self) {
-        let mut file = File::open("create_tables_dblp.sql").unwrap();
-        let mut query = String::new();
-        file.read_to_string(&mut query).unwrap();
-        self.client.batch_execute(&query).unwrap();
+    /// Creates the DBLP tables, replacing those of an earlier run against the same database.
+    pub fn create_tables_dblp(&mut self, schema: SchemaVariant, deferred: Deferred) -> Result<(), Box<dyn Error>> {
+        self.client
+            .batch_execute(&(drop_sql() + &schema.create_sql(deferred)))
+            .map_err(|e| format!("Failed to create the DBLP tables: {e}"))?;
         println!("Finished creating tables DBLP");
+        Ok(())
     }
 
-    pub fn insert_dblp_data(&mut self) {
-        let queries = [
-            ("Venues", VENUE_FILE),
-            ("Publishers", PUBLISHER_FILE),
-            ("Editors", EDITOR_FILE),
-            ("Authors", AUTHOR_FILE),
-            ("Publications", PUBLICATION_FILE),
-            ("Resources", RESOURCES_FILE),
-            ("PublicationEditors", PUBLICATION_EDITOR_FILE),
-            ("Reference", REFERENCE_FILE),
-            ("PublicationAuthors", PUBLICATION_AUTHORS_FILE),
-            ("AuthorWebsites", AUTHOR_WEBSITES_FILE),
-            ("Affiliations", AFFILIATIONS_FILE),
-            ("Alias", ALIAS_FILE)
-        ];
-        let mut transaction = self.client.transaction().unwrap();
-        for (table, file) in queries.iter() {
-            let mut file = File::open(file).unwrap();
-            let mut reader = BufReader::new(file);
-            let mut sink = transaction.copy_in(&format!("COPY {} FROM STDIN (FORMAT CSV, DELIMITER E'\\t', HEADER true)", table)).unwrap();
-            
-            let mut buffer = String::new();
-            loop {
-                let bytes_read = reader.read_line(&mut buffer).unwrap();
-                if bytes_read == 0 {
-                    break;
+    /// Adds the constraints left out when the tables were created, all keys before the foreign keys that
+    /// reference them, and logs how long that took.
+    pub fn add_deferred_constraints(&mut self, deferred: Deferred, jobs: usize) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        let tables = dblp_tables();
+        let mut steps: Vec<fn(&TableInfo) -> String> = Vec::new();
+        if deferred.keys {
+            steps.push(keys_sql);
+        }
+        if deferred.foreign_keys {
+            steps.push(foreign_keys_sql);
+        }
+        if steps.is_empty() {
+            return Ok(());
+        }
+        for step in steps {
+            let tables: Vec<&TableInfo> = tables.iter().filter(|table| !step(table).is_empty()).collect();
+            let add = |client: &mut Client, table: &TableInfo| -> Result<(), Box<dyn Error>> {
+                client
+                    .batch_execute(&step(table))
+                    .map_err(|e| format!("Failed to add the constraints of {}: {e}", table.name).into())
+            };
+            if jobs > 1 {
+                on_connections(&self.conn_str, jobs, &tables, add)?;
+            } else {
+                for table in tables {
+                    add(&mut self.client, table)?;
                 }
-                sink.write_all((&buffer).as_ref()).unwrap();
-                buffer.clear();
             }
-            sink.finish().unwrap();
         }
+        let duration = now.elapsed().as_millis();
+        self.append_log(&format!("Added deferred constraints\nDuration: {duration}\n"));
+        println!("Added deferred constraints in {duration} ms");
+        Ok(())
+    }
+
+    /// Reshapes the loaded DBLP tables into the schema variant.
+    pub fn transform_dblp(&mut self, schema: SchemaVariant) {
+        let query = schema.transform_sql();
+        if query.is_empty() {
+            return;
+        }
+        let mut transaction = self.client.transaction().unwrap();
+        transaction.batch_execute(&query).unwrap();
         transaction.commit().unwrap();
-        println!("Inserted DBLP data into Postgres");
+        println!("Transformed DBLP data into the {schema:?} schema");
+    }
+
+    /// Loads the DBLP tables and logs how long each table and the whole load took. One job loads all tables in a
+    /// single transaction, more jobs load the tables on connections of their own, each table in its own transaction.
+    pub fn insert_dblp_data(&mut self, dir: &str, format: OutputFormat, jobs: usize) -> Result<(), Box<dyn Error>> {
+        let options = copy_options(format)?;
+        let tables = dblp_tables();
+        let copy = |transaction: &mut Transaction, table: &TableInfo| -> Result<(), Box<dyn Error>> {
+            copy_file(transaction, table.name, &table.columns, &file_in(dir, &format.file(table.file)), options)
+                .map_err(|e| format!("Failed to load {}: {e}", table.name).into())
+        };
+        let now = Instant::now();
+        let durations = if jobs > 1 {
+            let tables: Vec<&TableInfo> = tables.iter().collect();
+            on_connections(&self.conn_str, jobs, &tables, |client, table| {
+                let mut transaction = client.transaction()?;
+                copy(&mut transaction, table)?;
+                Ok(transaction.commit()?)
+            })?
+        } else {
+            let mut durations = Vec::new();
+            let mut transaction = self.client.transaction()?;
+            for table in tables.iter() {
+                let start = Instant::now();
+                copy(&mut transaction, table)?;
+                durations.push((table.name, start.elapsed().as_millis()));
+            }
+            transaction.commit()?;
+            durations
+        };
+        let duration = now.elapsed().as_millis();
+        let mut log = String::new();
+        for (table, duration) in durations {
+            log += &format!("Loaded {table}\nDuration: {duration}\n");
+        }
+        self.append_log(&format!("{log}Loaded DBLP data with {jobs} jobs\nDuration: {duration}\n"));
+        println!("Inserted DBLP data into Postgres in {duration} ms");
+        Ok(())
+    }
+
+    fn append_log(&self, text: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/data/postgres.{}.log", self.dataset))
+            .unwrap();
+        let _ = file.write(text.as_bytes());
+    }
+
+    /// Applies the change sets of an incremental DBLP parse in `dir` in one transaction and logs how long that took.
+    pub fn apply_dblp_changes(&mut self, dir: &str) -> Result<(), Box<dyn Error>> {
+        let tables = dblp_tables();
+        let now = Instant::now();
+        let mut transaction = self.client.transaction()?;
+        // Owned rows go first, so no deleted record is still referenced
+        for table in tables.iter().rev() {
+            let Some(key) = table.changes.delete_key() else { continue };
+            let staging = format!("{}_deletes", table.name);
+            transaction.batch_execute(&staging_sql(table, &staging, &[key]))?;
+            copy_file(&mut transaction, &staging, &[key], &change_file(dir, table, ChangeKind::Delete), TSV_OPTIONS)?;
+            transaction.batch_execute(&format!("{}\nDROP TABLE {staging};", delete_sql(table, &staging, key)))?;
+        }
+        // Tables go in foreign key order, so new venues and publishers exist before the records referencing them
+        for table in tables.iter() {
+            if let Some(key) = table.changes.update_key() {
+                let staging = format!("{}_updates", table.name);
+                transaction.batch_execute(&staging_sql(table, &staging, &table.columns))?;
+                copy_file(&mut transaction, &staging, &table.columns, &change_file(dir, table, ChangeKind::Update), TSV_OPTIONS)?;
+                transaction.batch_execute(&format!("{}\nDROP TABLE {staging};", update_sql(table, &staging, key)))?;
+            }
+            copy_file(&mut transaction, table.name, &table.columns, &change_file(dir, table, ChangeKind::Insert), TSV_OPTIONS)?;
+        }
+        transaction.commit()?;
+        let duration = now.elapsed().as_millis();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/data/postgres.{}.log", self.dataset))?;
+        let _ = file.write(format!("Applied DBLP changes\nDuration: {duration}\n").as_bytes());
+        println!("Applied DBLP changes to Postgres in {duration} ms");
+        Ok(())
     }
     
-    pub fn run_test_query(&mut self, query: &str) -> u128 {
+    /// Runs the setup statements of the dataset and refreshes the planner statistics, logging how long each step took.
+    /// A failing statement fails the run, so it is never benchmarked without the index it should have created.
+    pub fn post_load(&mut self, setup: &[SetupRecord]) -> Result<(), Box<dyn Error>> {
         let now = Instant::now();
-        let result : Vec<Row> = self.client.query(query, &[]).unwrap();
+        let mut log = String::new();
+        for record in setup.iter().filter(|record| !record.postgresql.is_empty()) {
+            let start = Instant::now();
+            self.client
+                .batch_execute(&record.postgresql)
+                .map_err(|e| format!("Setup {} failed for Postgres: {e}", record.name))?;
+            log += &format!("Setup: {}\nDuration: {}\n", record.name, start.elapsed().as_millis());
+        }
+        let start = Instant::now();
+        self.client.batch_execute("VACUUM ANALYZE;")?;
+        log += &format!("Setup: VACUUM ANALYZE\nDuration: {}\n", start.elapsed().as_millis());
+        let duration = now.elapsed().as_millis();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/data/postgres.{}.log", self.dataset))
+            .unwrap();
+        let _ = file.write(format!("{log}Post-load setup\nDuration: {duration}\n").as_bytes());
+        println!("Finished post-load setup of Postgres in {duration} ms");
+        Ok(())
+    }
+
+    /// Image, server version, installed extensions and the values of the recorded and configured settings, as
+    /// (name, value).
+    pub fn settings(&mut self, configured: &[(String, String)]) -> Vec<(String, String)> {
+        let mut settings: Vec<(String, String)> = self.image.iter().map(|image| (String::from("image"), image.clone())).collect();
+        let version: String = self.client.query_one("SHOW server_version;", &[]).unwrap().get(0);
+        settings.push((String::from("server_version"), version));
+        let extensions = self.client.query("SELECT extname, extversion FROM pg_extension ORDER BY extname;", &[]).unwrap();
+        for row in extensions {
+            let (name, version): (String, String) = (row.get(0), row.get(1));
+            settings.push((format!("extension.{name}"), version));
+        }
+        let names = RECORDED_SETTINGS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(configured.iter().map(|(name, _)| name.clone()));
+        for name in names {
+            if settings.iter().any(|(recorded, _)| *recorded == name) {
+                continue;
+            }
+            // Setting names can't be bound as parameters, current_setting takes them as text
+            let value: String = self.client.query_one("SELECT current_setting($1);", &[&name]).unwrap().get(0);
+            settings.push((name, value));
+        }
+        settings
+    }
+
+    /// Runs the query with EXPLAIN (ANALYZE, BUFFERS) and summarizes its plan: planning and execution time in ms,
+    /// sequential scans, shared buffers hit and read and temporary blocks written by the whole plan, and nodes
+    /// that spilled to disk because they didn't fit into work_mem.
+    pub fn explain(&mut self, query: &str) -> Result<QueryPlan, Box<dyn Error>> {
+        let query = query.trim().trim_end_matches(';');
+        // The simple protocol returns every value as text, including the json the plan comes as
+        let messages = self.client.simple_query(&format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {query}"))?;
+        let text = messages
+            .iter()
+            .find_map(|message| match message {
+                SimpleQueryMessage::Row(row) => row.get(0),
+                _ => None,
+            })
+            .ok_or("EXPLAIN returned no plan")?;
+        let plan: Value = serde_json::from_str(text)?;
+        let root = &plan[0]["Plan"];
+        let mut seq_scans = 0;
+        let mut spills = 0;
+        visit_nodes(root, "Plans", &mut |node| {
+            if node["Node Type"] == "Seq Scan" {
+                seq_scans += 1;
+            }
+            let batches = |key: &str| node[key].as_u64().is_some_and(|batches| batches > 1);
+            if node["Sort Space Type"] == "Disk" || batches("Hash Batches") || batches("HashAgg Batches") {
+                spills += 1;
+            }
+        });
+        let figure = |value: &Value| value.as_f64().map(|value| value.to_string()).unwrap_or_default();
+        let summary = vec![
+            ("planning_ms", figure(&plan[0]["Planning Time"])),
+            ("execution_ms", figure(&plan[0]["Execution Time"])),
+            ("seq_scans", seq_scans.to_string()),
+            ("shared_hit_blocks", figure(&root["Shared Hit Blocks"])),
+            ("shared_read_blocks", figure(&root["Shared Read Blocks"])),
+            ("temp_written_blocks", figure(&root["Temp Written Blocks"])),
+            ("spilled_nodes", spills.to_string()),
+        ];
+        Ok(QueryPlan { plan, summary })
+    }
+
+    pub fn run_test_query(&mut self, query: &str) -> Result<u128, Box<dyn Error>> {
+        let now = Instant::now();
+        let result : Vec<Row> = self.client.query(query, &[])?;
         let duration = now.elapsed().as_millis();
         let mut file = OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open(format!("/data/postgres.{}.log", self.dataset))
             .unwrap();
         let mut columns = 0;
-        if let Some(row) = result.get(0) {
+        if let Some(row) = result.first() {
             columns = row.len();
         }
         let _ = file.write(
             format!("Query: {0}\nDuration: {1}\nResult Size: Columns {2} Rows {3}\n", query, duration, columns, result.len()).as_bytes()
         );
         
-        duration
+        Ok(duration)
     }
     
+    /// Stops and removes the container unless it is kept. Safe to call more than once, only the first call
+    /// does anything.
     pub fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        // A server given with --postgres-url isn't ours to stop
+        let Some(docker_id) = self.docker_id.take() else { return Ok(()) };
+        if self.keep_container {
+            println!("Kept Postgres container {docker_id}, connect with: {}", self.conn_str);
+            return Ok(());
+        }
         //Stop docker container
-        let rt = Runtime::new().unwrap();
-        let handle = rt.handle();
-
-        handle.block_on(async {
-            let docker = Docker::connect_with_defaults().unwrap();
-
-            docker.stop_container(
-                self.docker_id.as_str(),
-                None::<bollard::query_parameters::StopContainerOptions>
-            ).await.unwrap();
-
-            docker.remove_container(self.docker_id.as_str(), None::<bollard::query_parameters::RemoveContainerOptions>).await.unwrap();
-        });
-        
-        Ok(())
+        let rt = Runtime::new()?;
+        rt.block_on(async {
+            let docker = Docker::connect_with_defaults()?;
+            containers::remove(&docker, &docker_id).await
+        })
     }
 }
 
 impl Drop for PostgresConnection {
     fn drop(&mut self) {
-        self.close().unwrap();
+        // Panicking here would abort a thread that is already unwinding
+        if let Err(e) = self.close() {
+            eprintln!("Failed to remove the Postgres container: {e}");
+        }
     }
 }
 
-pub fn create_client() -> Client{
+// HEADER MATCH makes Postgres reject files whose header differs from the column list
+const TSV_OPTIONS: &str = "FORMAT CSV, DELIMITER E'\\t', HEADER MATCH";
+
+/// COPY options that read the DBLP tables in the given format.
+fn copy_options(format: OutputFormat) -> Result<&'static str, Box<dyn Error>> {
+    match format {
+        OutputFormat::Tsv => Ok(TSV_OPTIONS),
+        OutputFormat::Csv => Ok("FORMAT CSV, HEADER MATCH"),
+        OutputFormat::PgBinary => Ok("FORMAT BINARY"),
+        OutputFormat::Parquet => Err("Postgres can't load parquet files".into()),
+    }
+}
+
+/// Streams a table file into the given columns of a table.
+fn copy_file(transaction: &mut Transaction, table: &str, columns: &[&str], path: &str, options: &str) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let sink = transaction.copy_in(&format!("COPY {} ({}) FROM STDIN ({})", table, columns.join(", "), options))?;
+    // Every write is sent as a message of its own, so the file goes out in large chunks
+    let mut sink = BufWriter::with_capacity(COPY_BUFFER_SIZE, sink);
+    io::copy(&mut file, &mut sink)?;
+    sink.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
+
+/// Runs `work` for every table on up to `jobs` connections of their own and returns how long each table took in ms.
+/// The first error stops the workers from taking further tables and is returned once they are done.
+fn on_connections(
+    conn_str: &str,
+    jobs: usize,
+    tables: &[&TableInfo],
+    work: impl Fn(&mut Client, &TableInfo) -> Result<(), Box<dyn Error>> + Sync,
+) -> Result<Vec<(&'static str, u128)>, Box<dyn Error>> {
+    let next = AtomicUsize::new(0);
+    let durations = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(tables.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<(), String> {
+                    let mut client = create_client(conn_str).map_err(|e| e.to_string())?;
+                    while let Some(table) = tables.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let start = Instant::now();
+                        if let Err(e) = work(&mut client, table) {
+                            next.store(tables.len(), Ordering::Relaxed);
+                            return Err(e.to_string());
+                        }
+                        durations.lock().unwrap().push((table.name, start.elapsed().as_millis()));
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        // Every worker is joined before the first error is returned
+        let results: Vec<Result<(), String>> = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(String::from("a worker panicked"))))
+            .collect();
+        results.into_iter().collect::<Result<(), String>>()
+    })?;
+    Ok(durations.into_inner().unwrap())
+}
+
+/// Starts a Postgres container of the image with the configured server settings and returns its id.
+fn start_container(image: &str, config: &RunConfig) -> Result<(String, u16), Box<dyn Error>> {
+    let rt = Runtime::new()?;
+    let handle = rt.handle();
+    handle.block_on(async {
+        let docker = Docker::connect_with_defaults()?;
+
+        docker.create_image(
+            Some(
+                bollard::query_parameters::CreateImageOptionsBuilder::default()
+                    .from_image(image)
+                    .build(),
+            ),
+            None,
+            None,
+        )
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // Without a host port Docker picks a free one, so runs don't compete for 5432
+        let mut port_bindings = HashMap::new();
+        port_bindings.insert(
+            "5432/tcp".to_string(),
+            Some(vec![PortBinding {
+                host_ip: Some("0.0.0.0".to_string()),
+                host_port: None,
+            }]),
+        );
+
+        // Server settings are passed to the postgres command the image starts
+        let mut command = vec![String::from("postgres")];
+        for (name, value) in config.postgres_settings.iter() {
+            command.push(String::from("-c"));
+            command.push(format!("{name}={value}"));
+        }
+
+        let config = ContainerCreateBody {
+            image: Some(image.into()),
+            cmd: Some(command),
+            env: Some(vec![
+                "POSTGRES_PASSWORD=password".to_string(),
+                "POSTGRES_USER=postgres".to_string(),
+                "POSTGRES_DB=database".to_string()
+            ]),
+            labels: Some(containers::labels()),
+            // The server the image runs while initializing only listens on its socket, so checking over TCP
+            // waits for the real one
+            healthcheck: Some(HealthConfig {
+                test: Some(["CMD", "pg_isready", "-h", "127.0.0.1", "-U", "postgres", "-d", "database"].map(String::from).to_vec()),
+                interval: Some(1_000_000_000),
+                timeout: Some(5_000_000_000),
+                retries: Some(10),
+                start_period: Some(300_000_000_000),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                port_bindings: Some(port_bindings),
+                // Parallel workers exchange data through /dev/shm, which Docker limits to 64 MB
+                shm_size: Some(1 << 30),
+                ..Default::default()
+            }),
+            exposed_ports: Some( {
+                let mut ports = HashMap::new();
+                ports.insert("5432/tcp".to_string(),HashMap::new());
+                ports
+            }),
+            ..Default::default()
+        };
+
+        let options = CreateContainerOptionsBuilder::default().name(&containers::container_name("postgres")).build();
+
+        let id = docker.create_container(
+            Some(options),
+            config,
+        )
+            .await?
+            .id;
+        let started = async {
+            docker.start_container(&id, None::<bollard::query_parameters::StartContainerOptions>).await?;
+            containers::wait_until_healthy(&docker, &id, READY_TIMEOUT).await?;
+            containers::host_port(&docker, &id, "5432/tcp").await
+        };
+        match started.await {
+            Ok(port) => Ok((id, port)),
+            Err(e) => {
+                // Don't leave a container behind that no connection owns
+                let _ = containers::remove(&docker, &id).await;
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Connection string of a container started by start_container.
+fn container_conn_str(port: u16) -> String {
     let host = if cfg!(target_os = "linux") {
         "172.17.0.1"
     } else {
         "host.docker.internal"
     };
-    let conn_str = format!(
-        "user=postgres password=password host={} dbname=database",
-        host
-    );
-    // Connect to Postgres DB
-    let mut retries = 0;
-    let max_retries = 10;
-    let client: Client;
-    loop {
-        match Client::connect(&conn_str, NoTls) {
-            Ok(cli) => {
-                client = cli;
-                break;
-            },
-            Err(_) if  retries < max_retries => {
-                retries += 1;
-                sleep(Duration::from_secs(2));
-            },
-            Err(e) => {
-                panic!("Failed to connect to database: {}", e);
-            }
+    format!("user=postgres password=password host={host} port={port} dbname=database")
+}
+
+/// Connects to the server of the connection string, which may be in key=value or URI form. Without a password
+/// it is looked up in the password file like libpq does, so the same file works for psql and osm2pgsql.
+pub fn create_client(conn_str: &str) -> Result<Client, Box<dyn Error>> {
+    let mut config: Config = conn_str.parse()?;
+    if config.get_password().is_none()
+        && let Some(password) = pgpass_password(&config)
+    {
+        config.password(password);
+    }
+    // Like libpq, sslmode=require encrypts without verifying the server certificate, and the default
+    // sslmode=prefer falls back to an unencrypted connection if the server has no TLS
+    let tls = TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()?;
+    Ok(config.connect(MakeTlsConnector::new(tls))?)
+}
+
+/// Password of the first entry of PGPASSFILE or ~/.pgpass that matches host, port, database and user.
+fn pgpass_password(config: &Config) -> Option<String> {
+    let file = pgpass_file(|name| env::var(name).ok())?;
+    pgpass_lookup(&read_to_string(file).ok()?, config)
+}
+
+/// Location of the password file, given the environment variables by `var`.
+fn pgpass_file(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    var("PGPASSFILE").or_else(|| var("HOME").map(|home| format!("{home}/.pgpass")))
+}
+
+/// Password of the first line of a password file that matches the connection.
+fn pgpass_lookup(content: &str, config: &Config) -> Option<String> {
+    let host = match config.get_hosts().first() {
+        Some(Host::Tcp(host)) => host.clone(),
+        // libpq matches Unix sockets against localhost
+        _ => String::from("localhost"),
+    };
+    let port = config.get_ports().first().copied().unwrap_or(5432).to_string();
+    let user = config.get_user()?;
+    let dbname = config.get_dbname().unwrap_or(user);
+    let wanted = [host.as_str(), port.as_str(), dbname, user];
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(pgpass_fields)
+        .find(|fields| fields.len() == 5 && fields.iter().zip(wanted).all(|(field, value)| field == "*" || field == value))
+        .map(|mut fields| fields.remove(4))
+}
+
+/// Splits a password file line at its colons, where `\:` and `\\` are an escaped colon and backslash.
+fn pgpass_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
         }
     }
-    client
-}
\ No newline at end of file
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(content: &str, conn_str: &str) -> Option<String> {
+        pgpass_lookup(content, &conn_str.parse().unwrap())
+    }
+
+    fn config(setup: Vec<SetupRecord>, osm_loader: OsmLoader) -> RunConfig {
+        RunConfig {
+            incremental: false,
+            format: OutputFormat::Tsv,
+            rdf: None,
+            schema: SchemaVariant::Normalized,
+            setup,
+            load_jobs: 1,
+            defer_constraints: false,
+            osm_loader,
+            osm2pgsql: Osm2pgsqlOptions { style: None, slim: false, cache: None, processes: None, hstore: false },
+            keep_containers: false,
+            postgres_url: None,
+            postgres_image: None,
+            postgres_settings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn derives_extensions_from_setup() {
+        assert!(dataset_extensions("dblp", &config(Vec::new(), OsmLoader::Raw)).is_empty());
+        let setup = crate::read_setup_file(concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/setup.tsv"), "dblp", SchemaVariant::Normalized).unwrap();
+        assert_eq!(dataset_extensions("dblp", &config(setup, OsmLoader::Raw)), vec!["pg_trgm"]);
+        assert_eq!(dataset_extensions("osm-country", &config(Vec::new(), OsmLoader::Raw)), vec!["postgis", "hstore"]);
+        assert_eq!(dataset_extensions("osm-country", &config(Vec::new(), OsmLoader::Osm2pgsql)), vec!["postgis"]);
+    }
+
+    #[test]
+    fn splits_pgpass_lines() {
+        assert_eq!(pgpass_fields("db.example.org:5432:*:bob:secret"), vec!["db.example.org", "5432", "*", "bob", "secret"]);
+        assert_eq!(pgpass_fields(r"host:5432:db:bob:pa\:ss\\word"), vec!["host", "5432", "db", "bob", r"pa:ss\word"]);
+        // An escaped colon doesn't split the field, so the line has too few fields to match anything
+        assert_eq!(pgpass_fields(r"host\:5432:db:bob:secret").len(), 4);
+    }
+
+    #[test]
+    fn finds_pgpass_passwords() {
+        let content = "# comment:5432:*:bob:commented\n\
+                       db.example.org:5433:*:bob:other port\n\
+                       db.example.org:*:bench:bob:wildcard port\n\
+                       db.example.org:5432:*:bob:any database\n\
+                       localhost:5432:bob:bob:socket\n\
+                       *:*:*:*:fallback\n";
+        assert_eq!(lookup(content, "host=db.example.org port=5433 user=bob dbname=x").as_deref(), Some("other port"));
+        // The first matching line wins
+        assert_eq!(lookup(content, "host=db.example.org user=bob dbname=bench").as_deref(), Some("wildcard port"));
+        assert_eq!(lookup(content, "host=db.example.org user=bob dbname=x").as_deref(), Some("any database"));
+        // Unix sockets match localhost, and the database defaults to the user
+        assert_eq!(lookup(content, "host=/var/run/postgresql user=bob").as_deref(), Some("socket"));
+        assert_eq!(lookup(content, "host=db.example.org user=alice").as_deref(), Some("fallback"));
+        assert_eq!(lookup("db.example.org:5432:*:bob:secret", "host=other.example.org user=bob"), None);
+        // Without a user nothing can match
+        assert_eq!(lookup(content, "host=db.example.org"), None);
+    }
+
+    #[test]
+    fn locates_pgpass_file() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+        };
+        assert_eq!(pgpass_file(env(&[("HOME", "/home/bob")])).as_deref(), Some("/home/bob/.pgpass"));
+        assert_eq!(pgpass_file(env(&[("HOME", "/home/bob"), ("PGPASSFILE", "/etc/pgpass")])).as_deref(), Some("/etc/pgpass"));
+        assert_eq!(pgpass_file(env(&[])), None);
+    }
+}
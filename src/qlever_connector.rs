@@ -1,3 +1,6 @@
+use crate::containers;
+use crate::plans::{visit_nodes, QueryPlan};
+use crate::RunConfig;
 use std::collections::HashMap;
 use reqwest::header;
 use serde::Deserialize;
@@ -5,38 +8,50 @@ use std::fs;
 use std::error::Error;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::path::Path;
 use std::process::Command;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use bollard::Docker;
 use glob::glob;
 use regex::{Captures, Regex};
 use serde_json::Value;
 use tokio::runtime::Runtime;
 
+/// How long the server may take to load its index and answer queries.
+const READY_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub struct QLeverConnection {
-    docker_id: String,
+    /// None once the container is stopped.
+    docker_id: Option<String>,
+    /// Host port Docker published the server port on.
+    port: u16,
+    /// Leave the server running after the run.
+    keep_container: bool,
     qlever_file: QleverFile
 }
 
 impl QLeverConnection {
     
-    pub fn new(dataset: &String) -> Result<QLeverConnection, Box<dyn Error>> {
+    pub fn new(dataset: &str, config: &RunConfig) -> Result<QLeverConnection, Box<dyn Error>> {
         let mut qlever_file = QLeverConnection::setup_config(dataset);
         println!("Finished Setup Config");
         qlever_file.replace_internal_variables();
         // Create directory
         fs::create_dir(format!("/data/{}", qlever_file.data.get("NAME").unwrap()).as_str())?;
-        QLeverConnection::get_data(&qlever_file);
+        match config.rdf {
+            Some(rdf) if dataset.starts_with("dblp") => QLeverConnection::use_parsed_data(&mut qlever_file, rdf.file())?,
+            _ => QLeverConnection::get_data(&qlever_file),
+        }
         println!("Finished Fetching Data");
         QLeverConnection::index(&qlever_file);
         println!("Finished Indexing");
-        let conn = QLeverConnection::start(&qlever_file);
+        let conn = QLeverConnection::start(&qlever_file, config.keep_containers)?;
         println!("Finished startup");
         Ok(conn)
     }
     
-    fn setup_config(dataset: &String) -> QleverFile {
+    fn setup_config(dataset: &str) -> QleverFile {
         let dataset_parts: Vec<&str> = dataset.split(" ").collect();
         let target = match dataset_parts[0] {
             "dblp" => "https://raw.githubusercontent.com/ad-freiburg/qlever-control/refs/heads/main/src/qlever/Qleverfiles/Qleverfile.dblp",
@@ -47,7 +62,7 @@ impl QLeverConnection {
         let mut content = response.text().unwrap();
         content = Self::extra_args(dataset_parts, &content);
         let sanitizied = &Self::sanitize_toml(content);
-        return toml::from_str::<QleverFile>(sanitizied).unwrap();
+        toml::from_str::<QleverFile>(sanitizied).unwrap()
     }
 
     fn extra_args(dataset_parts: Vec<&str>, content: &String) -> String {
@@ -64,7 +79,7 @@ impl QLeverConnection {
                 let country_regex = Regex::new(r"COUNTRY\s*=\s(switzerland)").unwrap();
                 let mut new_content = continent_regex.replace(content.as_str(), continent_str).to_string();
                 new_content = country_regex.replace(new_content.as_str(), country_str).to_string();
-                return new_content
+                new_content
             }
             _ => unimplemented!()
         }
@@ -89,6 +104,17 @@ impl QLeverConnection {
         ).unwrap();
     }
     
+    /// Indexes the RDF written by our own parser instead of the dataset the Qleverfile downloads.
+    fn use_parsed_data(qlever_file: &mut QleverFile, rdf_file: &str) -> Result<(), Box<dyn Error>> {
+        let name = qlever_file.data.get("NAME").unwrap().clone();
+        let file_name = Path::new(rdf_file).file_name().unwrap().to_str().unwrap();
+        fs::copy(rdf_file, format!("/data/{name}/{file_name}"))?;
+        qlever_file.index.remove("MULTI_INPUT_JSON");
+        qlever_file.index.insert("CAT_INPUT_FILES".to_string(), format!("cat {file_name}"));
+        qlever_file.index.entry("STXXL_MEMORY".to_string()).or_insert_with(|| "5G".to_string());
+        Ok(())
+    }
+
     fn index(qlever_file: &QleverFile) {
         // create settings json
         let name = qlever_file.data.get("NAME").unwrap().as_str();
@@ -96,12 +122,15 @@ impl QLeverConnection {
         let mut file = File::create(path.clone()).unwrap();
         file.write_all(qlever_file.index.get("SETTINGS_JSON").unwrap().as_str().replace("\'","\"").as_bytes()).unwrap();
         // Create Index
+        let index_name = containers::container_name(&format!("qlever.index.{name}"));
+        let label = format!("{}={}", containers::RUN_LABEL, containers::run_id());
         let mut command = format!{
             "docker run --rm -u $(id -u):$(id -g) \
             -v /etc/localtime:/etc/localtime:ro \
             -v /data/{name}:/index \
             -w /index \
-            --name qlever.index.{name} \
+            --name {index_name} \
+            --label {label} \
             --init \
             --entrypoint bash \
             docker.io/adfreiburg/qlever:latest \
@@ -120,7 +149,7 @@ impl QLeverConnection {
             -s {name}.settings.json \
             --vocabulary-type {vocab}").as_str();
             let multi_json = qlever_file.index.get("MULTI_INPUT_JSON").unwrap().as_str();
-            let json: Value = serde_json::from_str(&multi_json.replace("\'","\"").as_str()).unwrap();
+            let json: Value = serde_json::from_str(multi_json.replace("\'","\"").as_str()).unwrap();
             let glob_cmd = format!("/data/{name}/{0}", json["for-each"].as_str().unwrap());
             for file in glob(glob_cmd.as_str()).unwrap() {
                 let file_path = file.unwrap();
@@ -150,8 +179,8 @@ impl QLeverConnection {
         command_assist("bash", &["-c", command.as_str()], ".").unwrap()
     }
     
-    fn start(qlever_file: &QleverFile) -> QLeverConnection {
-        // docker run -d --restart=unless-stopped
+    fn start(qlever_file: &QleverFile, keep_container: bool) -> Result<QLeverConnection, Box<dyn Error>> {
+        // docker run -d
         // -u $(id -u):$(id -g)
         // -v /etc/localtime:/etc/localtime:ro
         // -v $(pwd):/index
@@ -164,15 +193,19 @@ impl QLeverConnection {
         // 'ServerMain -i dblp -j 8 -p 7015 -m 10G -c 5G -e 1G -k 200 -s 300s -a dblp_yGJxTdx6CXRb > dblp.server-log.txt 2>&1'
         let name = qlever_file.data.get("NAME").unwrap().as_str();
         let port = qlever_file.server.get("PORT").unwrap().as_str();
+        let server_name = containers::container_name(&format!("qlever.server.{name}"));
+        let label = format!("{}={}", containers::RUN_LABEL, containers::run_id());
 
+        // Only the container port is given, Docker publishes it on a free host port
         let mut command = format!(
-            "docker run -d --restart=unless-stopped \
+            "docker run -d \
             -u $(id -u):$(id -g) \
             -v /etc/localtime:/etc/localtime:ro \
             -v /data/{name}:/index \
-            -p {port}:{port} \
+            -p {port} \
             -w /index \
-            --name qlever.server.{name} \
+            --name {server_name} \
+            --label {label} \
             --init \
             --entrypoint bash \
             docker.io/adfreiburg/qlever:latest -c 'ServerMain -i {name} -j 8 -p {port}"
@@ -201,55 +234,59 @@ impl QLeverConnection {
             command += format!(" -s {timeout}").as_str();
         }
         command += format!(" > /index/{name}.server-log.txt 2>&1'").as_str();
-        command_assist("bash", &["-c", command.as_str()], ".").unwrap();
+        command_assist("bash", &["-c", command.as_str()], ".")?;
+        let rt = Runtime::new()?;
+        let host_port = rt.block_on(async {
+            let docker = Docker::connect_with_defaults()?;
+            match containers::host_port(&docker, &server_name, &format!("{port}/tcp")).await {
+                Ok(port) => Ok(port),
+                Err(e) => {
+                    // Don't leave a container behind that no connection owns
+                    let _ = containers::remove(&docker, &server_name).await;
+                    Err(e)
+                }
+            }
+        })?;
         let mut conn = QLeverConnection {
             qlever_file: qlever_file.clone(),
-            docker_id: format!("qlever.server.{name}")
+            docker_id: Some(server_name),
+            port: host_port,
+            keep_container,
         };
-        // Test connection
+        // The image has no health check, the server is ready once it answers a query
         let test_request = "SELECT * WHERE {?s ?p ?o} LIMIT 1";
-        let mut times = 0;
-        while times < 12 {
-            let result = conn.do_query_request(test_request);
-            match result {
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    times += 1;
-                    print!("{}", e);
-                    sleep(Duration::from_secs(2))
-                }
+        let start = Instant::now();
+        while let Err(e) = conn.do_query_request(test_request) {
+            if start.elapsed() > READY_TIMEOUT {
+                conn.keep_container = false;
+                conn.stop()?;
+                return Err(format!("QLever server wasn't ready after {} s: {e}", READY_TIMEOUT.as_secs()).into());
             }
+            sleep(Duration::from_millis(500));
         }
 
-        conn
+        Ok(conn)
     }
     
-    pub fn stop(&self) -> Result<(), Box<dyn Error>> {
+    /// Stops and removes the server container unless it is kept. Safe to call more than once.
+    pub fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(docker_id) = self.docker_id.take() else { return Ok(()) };
+        if self.keep_container {
+            println!("Kept QLever container {docker_id}, listening on port {}", self.port);
+            return Ok(());
+        }
         //Stop docker container
-        let rt = Runtime::new().unwrap();
-        let handle = rt.handle();
-
-        handle.block_on(async {
-            let docker = Docker::connect_with_defaults().unwrap();
-
-            docker.stop_container(
-                self.docker_id.as_str(),
-                None::<bollard::query_parameters::StopContainerOptions>
-            ).await.unwrap();
-
-            docker.remove_container(self.docker_id.as_str(), None::<bollard::query_parameters::RemoveContainerOptions>).await.unwrap();
-        });
-
-        Ok(())
+        let rt = Runtime::new()?;
+        rt.block_on(async {
+            let docker = Docker::connect_with_defaults()?;
+            containers::remove(&docker, &docker_id).await
+        })
     }
 
-    pub fn run_test_query(&mut self, query: &str) -> u128 {
-        let result: (u128, usize, usize) = self.do_query_request(query).expect("query failed");
+    pub fn run_test_query(&mut self, query: &str) -> Result<u128, Box<dyn Error>> {
+        let result: (u128, usize, usize) = self.do_query_request(query)?;
         let name = self.qlever_file.data.get("NAME").unwrap().as_str();
         let mut file = OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open(format!("/data/qlever.{}.log", name))
@@ -257,11 +294,51 @@ impl QLeverConnection {
         let _ = file.write(
             format!("Query: {0}\nDuration: {1}\nResult Size: Columns {2} Rows {3}", query, result.0, result.2, result.1).as_bytes()
         );
-        result.0
+        Ok(result.0)
     }
     
     fn do_query_request(&mut self, query: &str) -> Result<(u128, usize, usize), Box<dyn Error>> {
-        let port = self.qlever_file.server.get("PORT").unwrap().as_str();
+        let result: JsonResult = self.send_query(query)?.json::<JsonResult>().expect("deserialize query result failed");
+        let time = parse_millis(&result.time.total).expect("parse time failed");
+        Ok((time, result.runtime.query_execution_tree.result_rows, result.runtime.query_execution_tree.result_cols))
+    }
+
+    /// Runs the query and summarizes the runtime information QLever answers with: total and compute time in ms,
+    /// index scans, operations answered from the cache and the size of the result.
+    pub fn explain(&mut self, query: &str) -> Result<QueryPlan, Box<dyn Error>> {
+        let mut response: Value = self.send_query(query)?.json()?;
+        // Unpinned results are evicted again, so the timed runs don't start with the answer cached
+        reqwest::blocking::Client::new()
+            .get(format!("http://127.0.0.1:{}/?cmd=clear-cache", self.port))
+            .send()?
+            .error_for_status()?;
+        let plan = response["runtimeInformation"].take();
+        let mut index_scans = 0;
+        let mut cached = 0;
+        visit_nodes(&plan["query_execution_tree"], "children", &mut |node| {
+            if node["description"].as_str().is_some_and(|description| description.starts_with("IndexScan")) {
+                index_scans += 1;
+            }
+            if node["cache_status"].as_str().is_some_and(|status| status != "computed") {
+                cached += 1;
+            }
+        });
+        let millis = |value: &Value| value.as_str().and_then(parse_millis).map(|time| time.to_string()).unwrap_or_default();
+        let figure = |value: &Value| value.as_u64().map(|value| value.to_string()).unwrap_or_default();
+        let tree = &plan["query_execution_tree"];
+        let summary = vec![
+            ("total_ms", millis(&response["time"]["total"])),
+            ("compute_ms", millis(&response["time"]["computeResult"])),
+            ("index_scans", index_scans.to_string()),
+            ("cached_operations", cached.to_string()),
+            ("result_rows", figure(&tree["result_rows"])),
+            ("result_cols", figure(&tree["result_cols"])),
+        ];
+        Ok(QueryPlan { plan, summary })
+    }
+
+    fn send_query(&self, query: &str) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+        let port = self.port;
         let mut headers = header::HeaderMap::new();
         headers.insert("Accept", "application/qlever-results+json".parse().unwrap());
         headers.insert("Content-type", "application/sparql-query".parse().unwrap());
@@ -275,30 +352,32 @@ impl QLeverConnection {
         let res = client.post(format!("http://127.0.0.1:{port}/"))
             .headers(headers)
             .body(query)
-            .send();
-        
-        if let Err(e) = res {
-            return Err(e.into());
+            .send()?;
+        Ok(res)
+    }
+}
+
+impl Drop for QLeverConnection {
+    fn drop(&mut self) {
+        // Panicking here would abort a thread that is already unwinding
+        if let Err(e) = self.stop() {
+            eprintln!("Failed to remove the QLever container: {e}");
         }
-        
-        let result: JsonResult = res.unwrap().json::<JsonResult>().expect("deserialize query result failed");
-        
-        let time: u128 = result.time.total.chars()
-            .take_while(|c| c.is_ascii_digit())
-            .collect::<String>()
-            .parse::<u128>()
-            .expect("parse time failed");
-        
-        Ok((time, result.runtime.query_execution_tree.result_rows, result.runtime.query_execution_tree.result_cols))
     }
 }
 
+/// Reads a QLever duration like `12ms`.
+fn parse_millis(time: &str) -> Option<u128> {
+    time.chars().take_while(|c| c.is_ascii_digit()).collect::<String>().parse().ok()
+}
+
 #[derive(Deserialize, Clone)]
 pub struct QleverFile {
     pub data: HashMap<String, String>,
     pub index: HashMap<String, String>,
     pub server: HashMap<String, String>,
     #[serde(flatten)]
+    #[allow(dead_code)]
     pub others: HashMap<String, toml::Value>,
 }
 
@@ -392,7 +471,7 @@ fn command_assist(command_str: &str, args: &[&str], current_dir: &str) -> Result
         .args(args)
         .current_dir(current_dir)
         .output()
-        .expect(("Failed executing command ".to_string() + command_str + " " + args.join(" ").as_str()).as_ref());
+        .unwrap_or_else(|e| panic!("Failed executing command {} {}: {e}", command_str, args.join(" ")));
     println!("executed_command: {}", command_str.to_string() + " " + args.join(" ").as_str());
     println!("status: {}", &command.status);
     println!("stdout:\n{}", String::from_utf8_lossy(&command.stdout));
@@ -0,0 +1,350 @@
+use crate::parser::{identifier_predicate, read_identifier, Contributor, Person, Publication, DBLP_SCHEMA};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDFS: &str = "http://www.w3.org/2000/01/rdf-schema#";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+const BIBTEX: &str = "http://purl.org/net/nknouf/ns/bibtex#";
+const CITO_CITES: &str = "http://purl.org/spar/cito/cites";
+const DBLP_PID: &str = "https://dblp.org/pid/";
+const DBLP_REC: &str = "https://dblp.org/rec/";
+
+/// Serialization of the RDF output. N-Triples is a subset of Turtle, so QLever reads both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RdfFormat {
+    NTriples,
+    /// Like N-Triples, but schema terms are abbreviated with prefixes.
+    Turtle,
+}
+
+impl RdfFormat {
+    pub const NAMES: [&'static str; 2] = ["nt", "ttl"];
+
+    pub fn from_name(name: &str) -> Option<RdfFormat> {
+        match name {
+            "nt" => Some(RdfFormat::NTriples),
+            "ttl" => Some(RdfFormat::Turtle),
+            _ => None,
+        }
+    }
+
+    /// Path of the RDF file next to the tables.
+    pub fn file(&self) -> &'static str {
+        match self {
+            RdfFormat::NTriples => "/data/dblp.nt",
+            RdfFormat::Turtle => "/data/dblp.ttl",
+        }
+    }
+}
+
+/// Writes DBLP records as triples following the dblp RDF schema, one triple per line.
+pub struct RdfWriter {
+    writer: BufWriter<File>,
+    format: RdfFormat,
+    next_signature: usize,
+}
+
+impl RdfWriter {
+    pub fn new(file: &str, format: RdfFormat) -> io::Result<RdfWriter> {
+        let mut writer = BufWriter::new(File::create(file)?);
+        if format == RdfFormat::Turtle {
+            writeln!(writer, "@prefix dblp: <{DBLP_SCHEMA}> .")?;
+            writeln!(writer, "@prefix rdfs: <{RDFS}> .")?;
+            writeln!(writer, "@prefix xsd: <{XSD}> .")?;
+        }
+        Ok(RdfWriter { writer, format, next_signature: 0 })
+    }
+
+    /// Writes a person record identified by its homepage key.
+    pub fn write_person(&mut self, key: &str, person: &Person) {
+        let subject = iri(&person_iri(key));
+        let name = literal(&dblp_name(&person.name, person.id));
+        self.triple(&subject, &self.rdf_type(), &self.dblp("Person"));
+        // Queries name people by their label, like in the dblp dump
+        self.triple(&subject, &self.label(), &name);
+        self.triple(&subject, &self.dblp("primaryCreatorName"), &name);
+        for (name, id) in person.alias.iter() {
+            self.triple(&subject, &self.dblp("creatorName"), &literal(&dblp_name(name, *id)));
+        }
+        for (affiliation, label) in person.affiliations.iter() {
+            let predicate = if label == "current" { "primaryAffiliation" } else { "affiliation" };
+            self.triple(&subject, &self.dblp(predicate), &literal(affiliation));
+        }
+        for url in person.urls.iter() {
+            let (predicate, object) = match read_identifier(url) {
+                Some((scheme, identifier)) if scheme == "orcid" => {
+                    (identifier_predicate(&scheme), format!("https://orcid.org/{identifier}"))
+                }
+                Some((scheme, _)) => (identifier_predicate(&scheme), url.clone()),
+                None => (format!("{DBLP_SCHEMA}webpage"), url.clone()),
+            };
+            self.triple(&subject, &iri(&predicate), &iri(&object));
+        }
+        // Awards, unames and other notes have no counterpart in the dblp schema
+    }
+
+    /// Writes a publication record with the person keys its authors and editors resolved to.
+    pub fn write_publication(&mut self, publication: &Publication, authors: &[Option<&str>], editors: &[Option<&str>]) {
+        let subject = iri(&format!("{DBLP_REC}{}", publication.key));
+        let (class, bibtex) = match publication.pubtype.as_str() {
+            "article" => (Some("Article"), "Article"),
+            "inproceedings" => (Some("Inproceedings"), "Inproceedings"),
+            "proceedings" => (Some("Editorship"), "Proceedings"),
+            "book" => (Some("Book"), "Book"),
+            "incollection" => (Some("Incollection"), "Incollection"),
+            "phdthesis" => (Some("Book"), "Phdthesis"),
+            "masterthesis" => (Some("Book"), "Mastersthesis"),
+            _ => (None, "Misc"),
+        };
+        self.triple(&subject, &self.rdf_type(), &self.dblp("Publication"));
+        if let Some(class) = class {
+            self.triple(&subject, &self.rdf_type(), &self.dblp(class));
+        }
+        self.triple(&subject, &self.dblp("bibtexType"), &iri(&format!("{BIBTEX}{bibtex}")));
+        self.triple(&subject, &self.label(), &literal(&publication.title));
+        self.triple(&subject, &self.dblp("title"), &literal(&publication.title));
+        if let Some(year) = publication.year {
+            self.triple(&subject, &self.dblp("yearOfPublication"), &self.typed(&year.to_string(), "gYear"));
+        }
+        if let Some(month) = &publication.month {
+            self.triple(&subject, &self.dblp("monthOfPublication"), &literal(month));
+        }
+        if let Some(journal) = &publication.journal {
+            self.triple(&subject, &self.dblp("publishedIn"), &literal(journal));
+            self.triple(&subject, &self.dblp("publishedInJournal"), &literal(journal));
+            if let Some(volume) = &publication.volume {
+                self.triple(&subject, &self.dblp("publishedInJournalVolume"), &literal(volume));
+            }
+            if let Some(number) = &publication.number {
+                self.triple(&subject, &self.dblp("publishedInJournalVolumeIssue"), &literal(number));
+            }
+        }
+        if let Some(book_title) = &publication.book_title {
+            self.triple(&subject, &self.dblp("publishedIn"), &literal(book_title));
+            if publication.pubtype == "incollection" {
+                self.triple(&subject, &self.dblp("publishedInBook"), &literal(book_title));
+            }
+        }
+        if let Some(pages) = &publication.pages {
+            self.triple(&subject, &self.dblp("pagination"), &literal(pages));
+        }
+        if let Some(publisher) = &publication.publisher {
+            self.triple(&subject, &self.dblp("publishedBy"), &literal(publisher));
+        }
+        if let Some(isbn) = &publication.isbn {
+            self.triple(&subject, &self.dblp("isbn"), &literal(isbn));
+        }
+        if let Some(school) = &publication.school {
+            self.triple(&subject, &self.dblp("thesisAcceptedBySchool"), &literal(school));
+        }
+        let mut primary_page = true;
+        for (resource_type, value) in publication.resources.iter() {
+            match resource_type.as_str() {
+                "ee" => {
+                    let predicate = if primary_page { "primaryDocumentPage" } else { "documentPage" };
+                    primary_page = false;
+                    self.triple(&subject, &self.dblp(predicate), &iri(value));
+                    if value.starts_with("https://doi.org/") {
+                        self.triple(&subject, &self.dblp("doi"), &iri(value));
+                    }
+                }
+                "url" if value.starts_with("db/") => {
+                    self.triple(&subject, &self.dblp("listedOnTocPage"), &iri(&format!("https://dblp.org/{value}")));
+                }
+                "series" => self.triple(&subject, &self.dblp("publishedInSeries"), &literal(value)),
+                _ => (),
+            }
+        }
+        for (reference_type, key) in publication.references.iter() {
+            let object = iri(&format!("{DBLP_REC}{key}"));
+            match reference_type.as_str() {
+                "crossref" => self.triple(&subject, &self.dblp("publishedAsPartOf"), &object),
+                _ => self.triple(&subject, &iri(CITO_CITES), &object),
+            }
+        }
+        self.write_signatures(&subject, &publication.authors, authors, "authoredBy", "AuthorSignature");
+        self.write_signatures(&subject, &publication.editor, editors, "editedBy", "EditorSignature");
+        let creators = publication.authors.len() + publication.editor.len();
+        self.triple(&subject, &self.dblp("numberOfCreators"), &self.typed(&creators.to_string(), "integer"));
+    }
+
+    /// Links the creators of a publication, directly and through a signature that keeps their position and name.
+    fn write_signatures(&mut self, subject: &str, contributors: &[Contributor], keys: &[Option<&str>], predicate: &str, class: &str) {
+        for (index, (contributor, key)) in contributors.iter().zip(keys.iter()).enumerate() {
+            let signature = format!("_:signature{}", self.next_signature);
+            self.next_signature += 1;
+            self.triple(subject, &self.dblp("hasSignature"), &signature);
+            self.triple(&signature, &self.rdf_type(), &self.dblp(class));
+            self.triple(&signature, &self.dblp("signaturePublication"), subject);
+            self.triple(&signature, &self.dblp("signatureDblpName"), &literal(&contributor.raw_name));
+            self.triple(&signature, &self.dblp("signatureOrdinal"), &self.typed(&(index + 1).to_string(), "integer"));
+            if let Some(orcid) = &contributor.orcid {
+                self.triple(&signature, &self.dblp("signatureOrcid"), &iri(&format!("https://orcid.org/{orcid}")));
+            }
+            // Names without a person record only appear in the signature
+            if let Some(key) = key {
+                let creator = iri(&person_iri(key));
+                self.triple(&signature, &self.dblp("signatureCreator"), &creator);
+                self.triple(subject, &self.dblp(predicate), &creator);
+                self.triple(subject, &self.dblp("createdBy"), &creator);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+
+    fn triple(&mut self, subject: &str, predicate: &str, object: &str) {
+        writeln!(self.writer, "{subject} {predicate} {object} .").unwrap();
+    }
+
+    fn rdf_type(&self) -> String {
+        match self.format {
+            RdfFormat::NTriples => iri(RDF_TYPE),
+            RdfFormat::Turtle => String::from("a"),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.format {
+            RdfFormat::NTriples => iri(&format!("{RDFS}label")),
+            RdfFormat::Turtle => String::from("rdfs:label"),
+        }
+    }
+
+    /// Term of the dblp schema.
+    fn dblp(&self, local: &str) -> String {
+        match self.format {
+            RdfFormat::NTriples => iri(&format!("{DBLP_SCHEMA}{local}")),
+            RdfFormat::Turtle => format!("dblp:{local}"),
+        }
+    }
+
+    fn typed(&self, value: &str, datatype: &str) -> String {
+        match self.format {
+            RdfFormat::NTriples => format!("{}^^{}", literal(value), iri(&format!("{XSD}{datatype}"))),
+            RdfFormat::Turtle => format!("{}^^xsd:{datatype}", literal(value)),
+        }
+    }
+}
+
+/// Person IRI of a homepage key, e.g. homepages/a/Alice becomes https://dblp.org/pid/a/Alice.
+fn person_iri(key: &str) -> String {
+    format!("{DBLP_PID}{}", key.trim_start_matches("homepages/"))
+}
+
+/// Name as dblp prints it, with the disambiguation number of homonyms.
+fn dblp_name(name: &str, id: usize) -> String {
+    if id == 0 {
+        name.to_string()
+    } else {
+        format!("{name} {id:04}")
+    }
+}
+
+/// IRI term, percent encoding the characters IRIs must not contain.
+fn iri(value: &str) -> String {
+    let mut term = String::from("<");
+    for c in value.chars() {
+        match c {
+            '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => term += &format!("%{:02X}", c as u32),
+            c if c <= ' ' => term += &format!("%{:02X}", c as u32),
+            c => term.push(c),
+        }
+    }
+    term.push('>');
+    term
+}
+
+fn literal(value: &str) -> String {
+    let mut term = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => term += "\\\"",
+            '\\' => term += "\\\\",
+            '\n' => term += "\\n",
+            '\r' => term += "\\r",
+            c => term.push(c),
+        }
+    }
+    term.push('"');
+    term
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fixture, scratch_dir};
+    use crate::formats::OutputFormat;
+    use crate::parser::{ParseMode, Parser};
+    use crate::tables::file_in;
+    use std::fs;
+
+    /// The triples of an N-Triples file as (subject, predicate, object) terms.
+    fn read_triples(file: &str) -> Vec<(String, String, String)> {
+        fs::read_to_string(file)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut terms = line.trim_end_matches(" .").splitn(3, ' ');
+                let mut term = || terms.next().unwrap().to_string();
+                (term(), term(), term())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_people_and_signatures() {
+        let dir = scratch_dir("rdf");
+        let mut parser = Parser::new(&fixture("dblp.xml"), &dir, ParseMode::Strict, OutputFormat::Tsv).unwrap();
+        parser.write_rdf(RdfFormat::NTriples).unwrap();
+        parser.run().unwrap();
+        let triples = read_triples(&file_in(&dir, RdfFormat::NTriples.file()));
+        let objects = |subject: &str, predicate: &str| -> Vec<String> {
+            let predicate = if predicate.starts_with("http") { iri(predicate) } else { iri(&format!("{DBLP_SCHEMA}{predicate}")) };
+            let mut objects: Vec<String> = triples
+                .iter()
+                .filter(|(s, p, _)| s == subject && *p == predicate)
+                .map(|(_, _, o)| o.clone())
+                .collect();
+            objects.sort();
+            objects
+        };
+        let label = format!("{RDFS}label");
+
+        let alice = iri("https://dblp.org/pid/a/Alice");
+        let bob = iri("https://dblp.org/pid/b/Bob");
+        assert_eq!(objects(&alice, RDF_TYPE), vec![iri(&format!("{DBLP_SCHEMA}Person"))]);
+        assert_eq!(objects(&alice, &label), vec![literal("Alice Smith")]);
+        assert_eq!(objects(&alice, "creatorName"), vec![literal("Alice S. Smith")]);
+        assert_eq!(objects(&alice, "orcid"), vec![iri("https://orcid.org/0000-0002-1825-0097")]);
+        assert_eq!(objects(&bob, &label), vec![literal("Bob Jones 0001")]);
+
+        let paper = iri("https://dblp.org/rec/conf/y/Jones19");
+        assert_eq!(objects(&paper, &label), vec![literal("Another Paper.")]);
+        assert_eq!(objects(&paper, "authoredBy"), vec![alice.clone(), bob.clone()]);
+        let signatures = objects(&paper, "hasSignature");
+        assert_eq!(signatures.len(), 2);
+        let signature = |ordinal: usize| -> &String {
+            let ordinal = format!("{}^^{}", literal(&ordinal.to_string()), iri(&format!("{XSD}integer")));
+            signatures.iter().find(|signature| objects(signature, "signatureOrdinal") == vec![ordinal.clone()]).unwrap()
+        };
+        // The alias resolves to Alice, whose label the signature's creator is named by in queries
+        let second = signature(2);
+        assert_eq!(objects(second, RDF_TYPE), vec![iri(&format!("{DBLP_SCHEMA}AuthorSignature"))]);
+        assert_eq!(objects(second, "signatureDblpName"), vec![literal("Alice S. Smith")]);
+        assert_eq!(objects(second, "signatureCreator"), vec![alice.clone()]);
+        assert_eq!(objects(second, "signaturePublication"), vec![paper.clone()]);
+        assert_eq!(objects(signature(1), "signatureCreator"), vec![bob]);
+
+        let paper = iri("https://dblp.org/rec/journals/x/Smith20");
+        let first = objects(&paper, "hasSignature")
+            .into_iter()
+            .find(|signature| objects(signature, "signatureCreator") == vec![alice.clone()])
+            .unwrap();
+        assert_eq!(objects(&first, "signatureOrcid"), vec![iri("https://orcid.org/0000-0002-1825-0097")]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::tables::{create_tables_dblp_sql, dblp_tables, drop_tables_dblp_sql, PublicationRow, Row, TableInfo};
+use crate::RunConfig;
+
+/// Relational layout the parsed DBLP tables are loaded into. Every variant loads the same files into
+/// the normalized tables and then reshapes them with SQL that both Postgres and DuckDB understand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaVariant {
+    /// The normalized tables with their keys and foreign keys.
+    Normalized,
+    /// The normalized tables with an index on every foreign key column.
+    Indexed,
+    /// The normalized tables without foreign keys.
+    NoForeignKeys,
+    /// Publications with their venue, publisher, authors and editors inlined.
+    Denormalized,
+    /// A single Triples(s, p, o) table, like the RDF data QLever loads.
+    Triples,
+}
+
+impl SchemaVariant {
+    pub const NAMES: [&'static str; 5] = ["normalized", "indexed", "no-fk", "denormalized", "triples"];
+
+    pub fn from_name(name: &str) -> Option<SchemaVariant> {
+        match name {
+            "normalized" => Some(SchemaVariant::Normalized),
+            "indexed" => Some(SchemaVariant::Indexed),
+            "no-fk" => Some(SchemaVariant::NoForeignKeys),
+            "denormalized" => Some(SchemaVariant::Denormalized),
+            "triples" => Some(SchemaVariant::Triples),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchemaVariant::Normalized => "normalized",
+            SchemaVariant::Indexed => "indexed",
+            SchemaVariant::NoForeignKeys => "no-fk",
+            SchemaVariant::Denormalized => "denormalized",
+            SchemaVariant::Triples => "triples",
+        }
+    }
+
+    /// Whether a comma separated list of variant names includes the variant. An empty list includes all of them.
+    pub fn listed_in(&self, names: &str) -> Result<bool, String> {
+        let mut names = names.split(',').map(str::trim).filter(|name| !name.is_empty()).peekable();
+        let mut listed = names.peek().is_none();
+        for name in names {
+            let variant = SchemaVariant::from_name(name).ok_or_else(|| format!("names the unknown schema {name}"))?;
+            listed |= variant == *self;
+        }
+        Ok(listed)
+    }
+
+    /// Creates the normalized tables the parsed files are loaded into, without the deferred constraints.
+    pub fn create_sql(&self, deferred: Deferred) -> String {
+        create_tables_dblp_sql(!deferred.keys, self.foreign_keys() && !deferred.foreign_keys)
+    }
+
+    /// Whether the normalized tables keep their foreign keys.
+    pub fn foreign_keys(&self) -> bool {
+        // Reshaped tables are dropped, which their foreign keys would prevent
+        matches!(self, SchemaVariant::Normalized | SchemaVariant::Indexed)
+    }
+
+    /// Turns the loaded normalized tables into the variant, empty if there is nothing to do.
+    pub fn transform_sql(&self) -> String {
+        match self {
+            SchemaVariant::Normalized | SchemaVariant::NoForeignKeys => String::new(),
+            SchemaVariant::Indexed => foreign_key_indexes_sql(),
+            SchemaVariant::Denormalized => denormalize_sql(),
+            SchemaVariant::Triples => triples_sql(),
+        }
+    }
+}
+
+/// Drops what an earlier load left in the database, whichever variant it was loaded into.
+pub fn drop_sql() -> String {
+    String::from("DROP TABLE IF EXISTS Triples;\nDROP TABLE IF EXISTS PublicationsWide;\n") + &drop_tables_dblp_sql()
+}
+
+/// Constraints that are added after the DBLP data is loaded instead of when the tables are created.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deferred {
+    /// Primary keys and unique constraints, which then aren't maintained row by row while loading.
+    pub keys: bool,
+    pub foreign_keys: bool,
+}
+
+impl Deferred {
+    pub fn of(config: &RunConfig) -> Deferred {
+        // Tables loaded in parallel can't check their foreign keys against tables that aren't committed yet
+        Deferred {
+            keys: config.defer_constraints,
+            foreign_keys: config.schema.foreign_keys() && (config.defer_constraints || config.load_jobs > 1),
+        }
+    }
+}
+
+/// Indexes the foreign key columns that aren't already the leading column of a key.
+fn foreign_key_indexes_sql() -> String {
+    let mut query = String::new();
+    for table in dblp_tables() {
+        for foreign_key in table.foreign_keys.iter() {
+            if table.keys().iter().any(|key| key[0] == foreign_key.column) {
+                continue;
+            }
+            query += &format!(
+                "CREATE INDEX {table}_{column}_idx ON {table}({column});\n",
+                table = table.name,
+                column = foreign_key.column
+            );
+        }
+    }
+    query
+}
+
+/// Replaces Publications by a wide table and drops the Venues and Publishers tables it absorbs.
+fn denormalize_sql() -> String {
+    let columns: Vec<String> = PublicationRow::header()
+        .into_iter()
+        .filter(|column| !matches!(*column, "venue_id" | "publisher_id"))
+        .map(|column| format!("p.{column}"))
+        .collect();
+    format!(
+        "CREATE TABLE PublicationsWide AS
+SELECT {}, v.name AS venue_name, v.type AS venue_type, pb.name AS publisher_name, a.authors, e.editors
+FROM Publications p
+LEFT JOIN Venues v ON v.id = p.venue_id
+LEFT JOIN Publishers pb ON pb.id = p.publisher_id
+LEFT JOIN (
+    SELECT publication_key, string_agg(raw_name, '; ' ORDER BY position) AS authors
+    FROM PublicationAuthors GROUP BY publication_key
+) a ON a.publication_key = p.key
+LEFT JOIN (
+    SELECT publication_key, string_agg(raw_name, '; ' ORDER BY position) AS editors
+    FROM PublicationEditors GROUP BY publication_key
+) e ON e.publication_key = p.key;
+DROP TABLE Publications;
+DROP TABLE Venues;
+DROP TABLE Publishers;
+ALTER TABLE PublicationsWide RENAME TO Publications;
+ALTER TABLE Publications ADD PRIMARY KEY (key);
+",
+        columns.join(", ")
+    )
+}
+
+/// Turns every row into a subject named after its table and primary key, with one triple per non null
+/// column. Foreign keys point to the subject of the referenced row, like IRIs do in RDF.
+fn triples_sql() -> String {
+    let tables = dblp_tables();
+    let mut query = String::from("CREATE TABLE Triples(s TEXT NOT NULL, p TEXT NOT NULL, o TEXT NOT NULL);\n");
+    for table in tables.iter() {
+        let subject = subject(table);
+        let primary_key = &table.primary_key;
+        for column in table.columns.iter() {
+            // A single key column is already part of the subject
+            if primary_key.len() == 1 && primary_key[0] == *column {
+                continue;
+            }
+            let object = match table.foreign_keys.iter().find(|foreign_key| foreign_key.column == *column) {
+                Some(foreign_key) => format!("'{}/' || CAST({column} AS TEXT)", foreign_key.table),
+                None => format!("CAST({column} AS TEXT)"),
+            };
+            query += &format!(
+                "INSERT INTO Triples SELECT {subject}, '{table}.{column}', {object} FROM {table} WHERE {column} IS NOT NULL;\n",
+                table = table.name
+            );
+        }
+    }
+    for table in tables.iter().rev() {
+        query += &format!("DROP TABLE {};\n", table.name);
+    }
+    query += "CREATE INDEX triples_sp_idx ON Triples(s, p);\nCREATE INDEX triples_p_idx ON Triples(p);\n";
+    query
+}
+
+fn subject(table: &TableInfo) -> String {
+    let key: Vec<String> = table.primary_key.iter().map(|column| format!("CAST({column} AS TEXT)")).collect();
+    format!("'{}/' || {}", table.name, key.join(" || '/' || "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_variants() {
+        assert_eq!(SchemaVariant::Triples.listed_in(""), Ok(true));
+        assert_eq!(SchemaVariant::Triples.listed_in("normalized, no-fk"), Ok(false));
+        assert_eq!(SchemaVariant::NoForeignKeys.listed_in("normalized, no-fk"), Ok(true));
+        assert_eq!(SchemaVariant::Normalized.listed_in("normalized,flat"), Err(String::from("names the unknown schema flat")));
+        for name in SchemaVariant::NAMES {
+            assert_eq!(SchemaVariant::from_name(name).unwrap().name(), name);
+        }
+    }
+
+    /// Loads the fixture into every variant and runs the queries of the dblp query file that apply to it,
+    /// which have to return what they return on the normalized tables.
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn queries_every_variant() {
+        use crate::duckdb_connector::DuckDBConnection;
+        use crate::fixtures::{parse_fixture, scratch_dir};
+        use crate::formats::OutputFormat;
+        use std::collections::HashMap;
+
+        let dir = scratch_dir("schemas");
+        parse_fixture("dblp.xml", &dir, OutputFormat::Tsv, None);
+        let queries = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/dblp.tsv");
+        let mut expected = HashMap::new();
+        for name in SchemaVariant::NAMES {
+            let schema = SchemaVariant::from_name(name).unwrap();
+            let mut conn = DuckDBConnection::in_memory();
+            conn.create_tables_dblp(schema);
+            conn.insert_dblp_data(&dir, OutputFormat::Tsv).unwrap();
+            conn.transform_dblp(schema);
+            let records = crate::read_test_file(queries, schema).unwrap();
+            assert_eq!(records.len(), 8, "{name}");
+            for record in records {
+                conn.run_test_query(&record.duckdb).unwrap_or_else(|e| panic!("{} on {name}: {e}", record.name));
+                let rows = conn.rows(&record.duckdb);
+                let expected = expected.entry(record.name.clone()).or_insert_with(|| rows.clone());
+                assert_eq!(&rows, expected, "{} on {name}", record.name);
+            }
+        }
+        assert_eq!(
+            expected["All papers until 1940"],
+            vec![vec![Some(String::from("Early Work.")), Some(String::from("1936")), Some(String::from("1")), Some(String::from("Bob Jones"))]]
+        );
+        assert!(!expected["First author of all papers"].is_empty());
+        assert!(!expected["Number of papers by venue"].is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::{AFFILIATIONS_FILE, ALIAS_FILE, AUTHOR_FILE, AUTHOR_IDENTIFIERS_FILE, AUTHOR_NOTES_FILE, AUTHOR_WEBSITES_FILE, AWARDS_FILE, EDITOR_FILE, PUBLICATION_AUTHORS_FILE, PUBLICATION_EDITOR_FILE, PUBLICATION_FILE, PUBLISHER_FILE, REFERENCE_FILE, REJECTS_FILE, RESOURCES_FILE, UNAMES_FILE, VENUE_FILE};
+use crate::formats::{OutputFormat, Sink};
+use csv::ReaderBuilder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Enum types used by the DBLP tables, created before the tables themselves.
+const DBLP_TYPES: &str = "CREATE TYPE pub_type AS ENUM('article', 'inproceedings', 'proceedings', 'book', 'incollection', 'phdthesis', 'masterthesis', 'www');
+CREATE TYPE venue_type AS ENUM ('journal', 'conference', 'book');
+CREATE TYPE ref_type AS ENUM ('crossref', 'cite');
+";
+
+/// A row of one of the generated DBLP tables.
+pub trait Row: Serialize {
+    /// Name of the SQL table.
+    const TABLE: &'static str;
+    /// Path of the tsv file the rows are written to.
+    const FILE: &'static str;
+
+    /// Column names and SQL definitions in the order the fields are serialized.
+    fn columns() -> Vec<(&'static str, &'static str)>;
+
+    /// Table level constraints such as composite keys and foreign keys.
+    fn constraints() -> Vec<&'static str>;
+
+    fn header() -> Vec<&'static str> {
+        Self::columns().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Creates the table, optionally leaving out its keys or foreign keys, which keys_sql and foreign_keys_sql
+    /// add once it is loaded.
+    fn create_table(keys: bool, foreign_keys: bool) -> String {
+        let mut definitions: Vec<String> = Self::columns()
+            .into_iter()
+            .map(|(name, definition)| match keys {
+                true => format!("    {name} {definition}"),
+                // A primary key column stays NOT NULL
+                false => format!("    {name} {}", definition.replace("PRIMARY KEY", "NOT NULL").replace("UNIQUE ", "")),
+            })
+            .collect();
+        definitions.extend(
+            Self::constraints()
+                .into_iter()
+                .filter(|constraint| match constraint.starts_with("FOREIGN KEY") {
+                    true => foreign_keys,
+                    false => keys,
+                })
+                .map(|constraint| format!("    {constraint}")),
+        );
+        format!("CREATE TABLE IF NOT EXISTS {}(\n{}\n);\n", Self::TABLE, definitions.join(",\n"))
+    }
+}
+
+/// Defines a row struct together with its table, output file and SQL columns,
+/// so the struct, the tsv header and the schema can't drift apart.
+macro_rules! table {
+    (
+        $row:ident, $table:expr, $file:expr,
+        { $($field:ident: $ty:ty => $sql:expr),* $(,)? }
+        $(, [$($constraint:expr),* $(,)?])?
+    ) => {
+        #[derive(Serialize, Deserialize, Debug)]
+        pub struct $row {
+            $(pub $field: $ty),*
+        }
+
+        impl Row for $row {
+            const TABLE: &'static str = $table;
+            const FILE: &'static str = $file;
+
+            fn columns() -> Vec<(&'static str, &'static str)> {
+                vec![$((stringify!($field).trim_start_matches("r#"), $sql)),*]
+            }
+
+            fn constraints() -> Vec<&'static str> {
+                vec![$($($constraint),*)?]
+            }
+        }
+    };
+}
+
+table!(VenueRow, "Venues", VENUE_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    name: Option<String> => "VARCHAR(255) NOT NULL",
+    r#type: Option<String> => "venue_type NOT NULL",
+}, ["UNIQUE (name, type)"]);
+
+table!(PublisherRow, "Publishers", PUBLISHER_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    name: Option<String> => "VARCHAR(255) UNIQUE NOT NULL",
+});
+
+table!(EditorRow, "Editors", EDITOR_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    name: String => "VARCHAR(255) UNIQUE NOT NULL",
+});
+
+table!(AuthorRow, "Authors", AUTHOR_FILE, {
+    key: usize => "INTEGER PRIMARY KEY",
+    name: String => "VARCHAR(255) NOT NULL",
+    id: usize => "INTEGER NOT NULL",
+    mdate: Option<String> => "DATE", // None for authors synthesized from publications
+    orcid: Option<String> => "VARCHAR(255)",
+}, ["UNIQUE (name, id)"]);
+
+table!(PublicationRow, "Publications", PUBLICATION_FILE, {
+    key: String => "VARCHAR(255) PRIMARY KEY",
+    mdate: String => "DATE NOT NULL",
+    title: String => "TEXT NOT NULL",
+    year: Option<usize> => "INTEGER",
+    month: Option<String> => "VARCHAR(255)",
+    r#type: String => "pub_type NOT NULL",
+    school: Option<String> => "VARCHAR(255)",
+    isbn: Option<String> => "VARCHAR(255)",
+    pages: Option<String> => "VARCHAR(255)",
+    volume: Option<String> => "VARCHAR(255)",
+    number: Option<String> => "VARCHAR(255)",
+    venue_id: Option<usize> => "INT",
+    publisher_id: Option<usize> => "INT",
+}, [
+    "FOREIGN KEY (venue_id) REFERENCES Venues(id)",
+    "FOREIGN KEY (publisher_id) REFERENCES Publishers(id)",
+]);
+
+table!(ResourceRow, "Resources", RESOURCES_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    r#type: String => "VARCHAR(255) NOT NULL",
+    value: String => "TEXT NOT NULL",
+    publication_key: String => "VARCHAR(255)",
+}, ["FOREIGN KEY (publication_key) REFERENCES Publications(key)"]);
+
+table!(PublicationEditorRow, "PublicationEditors", PUBLICATION_EDITOR_FILE, {
+    publication_key: String => "VARCHAR(255)",
+    editor_id: usize => "INT",
+    position: usize => "INT NOT NULL",
+    raw_name: String => "VARCHAR(255) NOT NULL",
+    orcid: Option<String> => "VARCHAR(255)",
+}, [
+    "PRIMARY KEY (publication_key, position)",
+    "FOREIGN KEY (publication_key) REFERENCES Publications(key)",
+    "FOREIGN KEY (editor_id) REFERENCES Editors(id)",
+]);
+
+table!(ReferenceRow, "Reference", REFERENCE_FILE, {
+    r#type: String => "ref_type",
+    origin_pub: String => "VARCHAR(255)",
+    dest_pub: String => "VARCHAR(255)",
+}, [
+    "PRIMARY KEY (type, origin_pub, dest_pub)",
+    "FOREIGN KEY (origin_pub) REFERENCES Publications(key)",
+    "FOREIGN KEY (dest_pub) REFERENCES Publications(key)",
+]);
+
+table!(PublicationAuthorRow, "PublicationAuthors", PUBLICATION_AUTHORS_FILE, {
+    publication_key: String => "VARCHAR(255)",
+    author_id: usize => "INT",
+    position: usize => "INT NOT NULL",
+    raw_name: String => "VARCHAR(255) NOT NULL",
+    orcid: Option<String> => "VARCHAR(255)",
+}, [
+    "PRIMARY KEY (publication_key, position)",
+    "FOREIGN KEY (publication_key) REFERENCES Publications(key)",
+    "FOREIGN KEY (author_id) REFERENCES Authors(key)",
+]);
+
+table!(AuthorWebsiteRow, "AuthorWebsites", AUTHOR_WEBSITES_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    url: String => "TEXT",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(AffiliationRow, "Affiliations", AFFILIATIONS_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    affiliation: String => "TEXT",
+    r#type: String => "VARCHAR(255)",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(AliasRow, "Alias", ALIAS_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    alias: String => "VARCHAR(255)",
+    alias_id: usize => "INT",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(AwardRow, "Awards", AWARDS_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    award: String => "TEXT NOT NULL",
+    label: Option<String> => "VARCHAR(255)",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(UnameRow, "AuthorUnames", UNAMES_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    uname: String => "VARCHAR(255) NOT NULL",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(AuthorNoteRow, "AuthorNotes", AUTHOR_NOTES_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    r#type: String => "VARCHAR(255) NOT NULL",
+    note: String => "TEXT",
+}, ["FOREIGN KEY (author_id) REFERENCES Authors(key)"]);
+
+table!(AuthorIdentifierRow, "AuthorIdentifiers", AUTHOR_IDENTIFIERS_FILE, {
+    id: usize => "INTEGER PRIMARY KEY",
+    author_id: usize => "INT",
+    scheme: String => "VARCHAR(255) NOT NULL",
+    identifier: String => "VARCHAR(255) NOT NULL",
+    predicate: String => "VARCHAR(255) NOT NULL",
+}, [
+    "UNIQUE (author_id, scheme, identifier)",
+    "FOREIGN KEY (author_id) REFERENCES Authors(key)",
+]);
+
+// Log of records the parser rejected, not loaded into the databases
+table!(RejectRow, "Rejects", REJECTS_FILE, {
+    key: Option<String> => "VARCHAR(255)",
+    reason: String => "VARCHAR(255) NOT NULL",
+    detail: String => "TEXT",
+    offset: u64 => "BIGINT NOT NULL",
+});
+
+/// How the rows of a table change between two parses of the DBLP dump.
+#[derive(Clone, Copy, Debug)]
+pub enum Changes {
+    /// Rows are records identified by the key column and are updated in place.
+    /// The identity columns determine the key of a record, so an update never changes them.
+    Record { key: &'static str, identity: &'static [&'static str] },
+    /// Rows belong to a record of the parent table through the owner column and are replaced along with it.
+    OwnedBy { column: &'static str, parent: &'static str },
+    /// Rows identified by the key column that are only ever added.
+    Lookup(&'static str),
+}
+
+impl Changes {
+    /// Column the deletes of the table are keyed by, if rows of the table are ever deleted.
+    pub fn delete_key(&self) -> Option<&'static str> {
+        match self {
+            Changes::Record { key, .. } => Some(key),
+            Changes::OwnedBy { column, .. } => Some(column),
+            Changes::Lookup(_) => None,
+        }
+    }
+
+    /// Column the updates of the table are keyed by, if rows of the table are ever updated.
+    pub fn update_key(&self) -> Option<&'static str> {
+        match self {
+            Changes::Record { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// A foreign key of a single column.
+#[derive(Clone, Copy, Debug)]
+pub struct ForeignKey {
+    pub column: &'static str,
+    pub table: &'static str,
+    pub references: &'static str,
+}
+
+/// Name, file, columns and schema of a table, used by the connectors to create and load it.
+pub struct TableInfo {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub columns: Vec<&'static str>,
+    /// Creates the table, with or without its keys and its foreign keys.
+    pub create: fn(bool, bool) -> String,
+    pub changes: Changes,
+    /// Empty if the table has no primary key.
+    pub primary_key: Vec<&'static str>,
+    pub unique: Vec<Vec<&'static str>>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+impl TableInfo {
+    fn of<T: Row>(changes: Changes) -> TableInfo {
+        let (primary_key, unique, foreign_keys) = constraints_of::<T>();
+        TableInfo {
+            name: T::TABLE,
+            file: T::FILE,
+            columns: T::header(),
+            create: T::create_table,
+            changes,
+            primary_key,
+            unique,
+            foreign_keys,
+        }
+    }
+
+    /// The primary key, if there is one, followed by the unique constraints.
+    pub fn keys(&self) -> Vec<Vec<&'static str>> {
+        let primary_key = Some(self.primary_key.clone()).filter(|key| !key.is_empty());
+        primary_key.into_iter().chain(self.unique.iter().cloned()).collect()
+    }
+}
+
+/// Primary key, unique constraints and foreign keys of a table, read from its column definitions and constraints.
+fn constraints_of<T: Row>() -> (Vec<&'static str>, Vec<Vec<&'static str>>, Vec<ForeignKey>) {
+    let mut primary_key = Vec::new();
+    let mut unique = Vec::new();
+    let mut foreign_keys = Vec::new();
+    for (name, definition) in T::columns() {
+        if definition.contains("PRIMARY KEY") {
+            assert!(primary_key.is_empty(), "{} has more than one primary key", T::TABLE);
+            primary_key.push(name);
+        } else if definition.contains("UNIQUE") {
+            unique.push(vec![name]);
+        }
+    }
+    for constraint in T::constraints() {
+        // Constraints look like PRIMARY KEY (a, b), UNIQUE (a, b) or FOREIGN KEY (a) REFERENCES Table(b)
+        let (kind, rest) = constraint.split_once('(').expect("constraint without columns");
+        let (columns, rest) = rest.split_once(')').expect("constraint without columns");
+        let columns: Vec<&'static str> = columns.split(',').map(str::trim).collect();
+        match kind.trim() {
+            "PRIMARY KEY" => {
+                assert!(primary_key.is_empty(), "{} has more than one primary key", T::TABLE);
+                primary_key = columns;
+            }
+            "UNIQUE" => unique.push(columns),
+            "FOREIGN KEY" => {
+                let (table, column) = rest.trim().trim_start_matches("REFERENCES").split_once('(').unwrap();
+                foreign_keys.push(ForeignKey {
+                    column: columns[0],
+                    table: table.trim(),
+                    references: column.trim_end_matches(')').trim(),
+                });
+            }
+            kind => panic!("unknown constraint {kind}"),
+        }
+    }
+    (primary_key, unique, foreign_keys)
+}
+
+/// All DBLP tables in an order that satisfies their foreign keys.
+pub fn dblp_tables() -> Vec<TableInfo> {
+    let publication = Changes::OwnedBy { column: "publication_key", parent: PublicationRow::TABLE };
+    let author = Changes::OwnedBy { column: "author_id", parent: AuthorRow::TABLE };
+    vec![
+        TableInfo::of::<VenueRow>(Changes::Lookup("id")),
+        TableInfo::of::<PublisherRow>(Changes::Lookup("id")),
+        TableInfo::of::<EditorRow>(Changes::Lookup("id")),
+        TableInfo::of::<AuthorRow>(Changes::Record { key: "key", identity: &["name", "id"] }),
+        TableInfo::of::<PublicationRow>(Changes::Record { key: "key", identity: &[] }),
+        TableInfo::of::<ResourceRow>(publication),
+        TableInfo::of::<PublicationEditorRow>(publication),
+        TableInfo::of::<ReferenceRow>(Changes::OwnedBy { column: "origin_pub", parent: PublicationRow::TABLE }),
+        TableInfo::of::<PublicationAuthorRow>(publication),
+        TableInfo::of::<AuthorWebsiteRow>(author),
+        TableInfo::of::<AffiliationRow>(author),
+        TableInfo::of::<AliasRow>(author),
+        TableInfo::of::<AwardRow>(author),
+        TableInfo::of::<UnameRow>(author),
+        TableInfo::of::<AuthorNoteRow>(author),
+        TableInfo::of::<AuthorIdentifierRow>(author),
+    ]
+}
+
+/// Path of a table file inside another directory, such as the output of a previous parse.
+pub fn file_in(dir: &str, file: &str) -> String {
+    let name = Path::new(file).file_name().unwrap();
+    Path::new(dir).join(name).to_string_lossy().into_owned()
+}
+
+/// Reads back all rows of a table written to `dir`.
+pub fn read_rows<T: Row + DeserializeOwned>(dir: &str) -> Result<Vec<T>, csv::Error> {
+    ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(file_in(dir, T::FILE))?
+        .deserialize()
+        .collect()
+}
+
+/// Schema of the DBLP tables, generated from the row definitions.
+pub fn create_tables_dblp_sql(keys: bool, foreign_keys: bool) -> String {
+    let mut query = String::from(DBLP_TYPES);
+    for table in dblp_tables() {
+        query += "\n";
+        query += (table.create)(keys, foreign_keys).as_str();
+    }
+    query
+}
+
+/// Drops the DBLP tables and types, tables referencing others first.
+pub fn drop_tables_dblp_sql() -> String {
+    let mut query = String::new();
+    for table in dblp_tables().iter().rev() {
+        query += &format!("DROP TABLE IF EXISTS {};\n", table.name);
+    }
+    query + "DROP TYPE IF EXISTS pub_type;\nDROP TYPE IF EXISTS venue_type;\nDROP TYPE IF EXISTS ref_type;\n"
+}
+
+/// Adds the primary key and unique constraints of a table created without them.
+pub fn keys_sql(table: &TableInfo) -> String {
+    let mut query = String::new();
+    if !table.primary_key.is_empty() {
+        query += &format!("ALTER TABLE {} ADD PRIMARY KEY ({});\n", table.name, table.primary_key.join(", "));
+    }
+    for key in table.unique.iter() {
+        query += &format!("ALTER TABLE {} ADD UNIQUE ({});\n", table.name, key.join(", "));
+    }
+    query
+}
+
+/// Adds the foreign keys of a table created without them. The referenced keys have to exist already.
+pub fn foreign_keys_sql(table: &TableInfo) -> String {
+    let mut query = String::new();
+    for foreign_key in table.foreign_keys.iter() {
+        query += &format!(
+            "ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {}({});\n",
+            table.name, foreign_key.column, foreign_key.table, foreign_key.references
+        );
+    }
+    query
+}
+
+/// Buffered writer for a single table in the chosen output format.
+/// The header is written when the writer is created.
+pub struct TableWriter<T: Row> {
+    sink: Sink,
+    rows: PhantomData<T>,
+}
+
+impl<T: Row> TableWriter<T> {
+    /// Creates the file of the table in `dir`.
+    pub fn new(dir: &str, format: OutputFormat) -> io::Result<TableWriter<T>> {
+        Ok(TableWriter {
+            sink: Sink::new(format, &file_in(dir, T::FILE), &T::columns())?,
+            rows: PhantomData,
+        })
+    }
+
+    pub fn write(&mut self, row: T) -> io::Result<()> {
+        self.sink.write(&row)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    /// Completes the file, no rows can be written afterwards.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{parse_fixture, scratch_dir};
+
+    /// Reads the parsed rows back by their header, which only yields the written values if the header
+    /// names the columns in the order the rows are serialized.
+    #[test]
+    fn headers_match_rows() {
+        let dir = scratch_dir("tables_headers");
+        parse_fixture("dblp.xml", &dir, OutputFormat::Tsv, None);
+
+        let authors = read_rows::<AuthorRow>(&dir).unwrap();
+        let alice = authors.iter().find(|author| author.name == "Alice Smith").unwrap();
+        assert_eq!((alice.id, alice.mdate.as_deref()), (0, Some("2023-01-01")));
+        assert_eq!(alice.orcid.as_deref(), Some("0000-0002-1825-0097"));
+        let bob = authors.iter().find(|author| author.name == "Bob Jones").unwrap();
+        assert_eq!(bob.id, 1);
+
+        let identifiers = read_rows::<AuthorIdentifierRow>(&dir).unwrap();
+        assert_eq!(identifiers.len(), 1);
+        assert_eq!(identifiers[0].author_id, alice.key);
+        assert_eq!((identifiers[0].scheme.as_str(), identifiers[0].identifier.as_str()), ("orcid", "0000-0002-1825-0097"));
+
+        let publication = read_rows::<PublicationRow>(&dir)
+            .unwrap()
+            .into_iter()
+            .find(|publication| publication.key == "journals/x/Smith20")
+            .unwrap();
+        assert_eq!((publication.title.as_str(), publication.year), ("A Paper.", Some(2020)));
+        assert_eq!((publication.r#type.as_str(), publication.volume.as_deref()), ("article", Some("1")));
+        let venue = read_rows::<VenueRow>(&dir)
+            .unwrap()
+            .into_iter()
+            .find(|venue| Some(venue.id) == publication.venue_id)
+            .unwrap();
+        assert_eq!((venue.name.as_deref(), venue.r#type.as_deref()), (Some("J. X"), Some("journal")));
+    }
+
+    table!(TagRow, "Tags", "tags.tsv", {
+        name: String => "VARCHAR(255) UNIQUE NOT NULL",
+        venue_id: usize => "INT",
+    }, ["FOREIGN KEY (venue_id) REFERENCES Venues(id)"]);
+
+    #[test]
+    fn defers_keys() {
+        let tags = TableInfo::of::<TagRow>(Changes::Lookup("name"));
+        assert!(tags.primary_key.is_empty());
+        assert_eq!(keys_sql(&tags), "ALTER TABLE Tags ADD UNIQUE (name);\n");
+        assert!(TagRow::create_table(true, false).contains("name VARCHAR(255) UNIQUE NOT NULL,\n    venue_id INT\n"));
+
+        let venues = TableInfo::of::<VenueRow>(Changes::Lookup("id"));
+        assert_eq!(venues.keys(), vec![vec!["id"], vec!["name", "type"]]);
+        assert_eq!(
+            keys_sql(&venues),
+            "ALTER TABLE Venues ADD PRIMARY KEY (id);\nALTER TABLE Venues ADD UNIQUE (name, type);\n"
+        );
+        let created = PublicationAuthorRow::create_table(false, false);
+        assert!(!created.contains("KEY") && !created.contains("UNIQUE"), "{created}");
+        let created = PublicationAuthorRow::create_table(false, true);
+        assert!(!created.contains("PRIMARY KEY") && created.contains("FOREIGN KEY"), "{created}");
+        assert!(AuthorRow::create_table(false, false).contains("key INTEGER NOT NULL"));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::formats::OutputFormat;
+use crate::tables::{dblp_tables, file_in, TableInfo};
+use csv::{ReaderBuilder, Writer, WriterBuilder};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+
+/// Directory inside the output directory that quarantined rows are moved to.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// What happens to rows that violate a key or foreign key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntegrityPolicy {
+    /// Remove the rows from their table.
+    Drop,
+    /// Move the rows to a file of the same name in the quarantine directory.
+    Quarantine,
+    /// Only report the rows, loading them will fail on the violated constraint.
+    Keep,
+}
+
+impl IntegrityPolicy {
+    pub const NAMES: [&'static str; 3] = ["drop", "quarantine", "keep"];
+
+    pub fn from_name(name: &str) -> Option<IntegrityPolicy> {
+        match name {
+            "drop" => Some(IntegrityPolicy::Drop),
+            "quarantine" => Some(IntegrityPolicy::Quarantine),
+            "keep" => Some(IntegrityPolicy::Keep),
+            _ => None,
+        }
+    }
+}
+
+/// Violations found in a table.
+#[derive(Debug, Default)]
+pub struct Violations {
+    /// Rows repeating the values of a key of an earlier row.
+    pub duplicate_keys: usize,
+    /// Rows per foreign key column whose value isn't a key of the referenced table.
+    pub dangling: BTreeMap<&'static str, usize>,
+}
+
+impl Violations {
+    pub fn is_empty(&self) -> bool {
+        self.duplicate_keys == 0 && self.dangling.is_empty()
+    }
+}
+
+/// Checks that the keys of every DBLP table in `dir` are unique and that every foreign key points to an existing
+/// row, and applies the policy to the rows that don't. Tables are checked in foreign key order, so rows referencing
+/// a dropped or quarantined row are treated the same way. Rows owned by a duplicate record carry the same key as the
+/// ones of the first record, so they stay and end up attached to it.
+pub fn validate_dblp(
+    dir: &str,
+    format: OutputFormat,
+    policy: IntegrityPolicy,
+) -> Result<Vec<(&'static str, Violations)>, Box<dyn Error>> {
+    let delimiter = format
+        .delimiter()
+        .ok_or_else(|| format!("validating the tables needs tsv or csv files, not {}", format.name()))?;
+    let tables = dblp_tables();
+    // Values of the referenced columns of the rows that stay in their table
+    let mut referenced: HashMap<(&'static str, &'static str), HashSet<String>> = tables
+        .iter()
+        .flat_map(|table| table.foreign_keys.iter())
+        .map(|foreign_key| ((foreign_key.table, foreign_key.references), HashSet::new()))
+        .collect();
+    if policy == IntegrityPolicy::Quarantine {
+        fs::create_dir_all(Path::new(dir).join(QUARANTINE_DIR))?;
+    }
+
+    let mut summary = Vec::new();
+    for table in tables.iter() {
+        let file = format.file(&file_in(dir, table.file));
+        let mut reader = ReaderBuilder::new().delimiter(delimiter).from_path(&file)?;
+        if reader.headers()? != table.columns.as_slice() {
+            return Err(format!("columns of {file} don't match the {} table", table.name).into());
+        }
+        let mut output = match policy {
+            IntegrityPolicy::Keep => None,
+            _ => Some(create(&format!("{file}.valid"), delimiter, &table.columns)?),
+        };
+        let mut quarantine = match policy {
+            IntegrityPolicy::Quarantine => {
+                Some(create(&file_in(&format!("{dir}/{QUARANTINE_DIR}"), &file), delimiter, &table.columns)?)
+            }
+            _ => None,
+        };
+
+        let keys = column_indices(table, &table.keys());
+        let mut seen: Vec<HashSet<Vec<String>>> = vec![HashSet::new(); keys.len()];
+        let foreign_keys: Vec<(usize, &HashSet<String>)> = table
+            .foreign_keys
+            .iter()
+            .map(|foreign_key| (column_index(table, foreign_key.column), &referenced[&(foreign_key.table, foreign_key.references)]))
+            .collect();
+        // Columns of this table that later tables reference, with the values of the kept rows
+        let mut keys_of_kept: Vec<(usize, HashSet<String>)> = table
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| referenced.contains_key(&(table.name, **column)))
+            .map(|(index, _)| (index, HashSet::new()))
+            .collect();
+        let mut violations = Violations::default();
+        for record in reader.records() {
+            let record = record?;
+            let mut valid = true;
+            for (index, parent_keys) in foreign_keys.iter() {
+                // An empty field is NULL, which references nothing
+                if !record[*index].is_empty() && !parent_keys.contains(&record[*index]) {
+                    *violations.dangling.entry(table.columns[*index]).or_insert(0) += 1;
+                    valid = false;
+                }
+            }
+            // A row is only a duplicate if it would have been kept
+            if valid {
+                for (key, seen) in keys.iter().zip(seen.iter_mut()) {
+                    let values: Vec<String> = key.iter().map(|index| record[*index].to_string()).collect();
+                    // Like NULLs in SQL, keys with an empty field never collide
+                    if values.iter().all(|value| !value.is_empty()) && !seen.insert(values) {
+                        violations.duplicate_keys += 1;
+                        valid = false;
+                        break;
+                    }
+                }
+            }
+            if valid || policy == IntegrityPolicy::Keep {
+                if let Some(output) = output.as_mut() {
+                    output.write_record(&record)?;
+                }
+                for (index, values) in keys_of_kept.iter_mut() {
+                    values.insert(record[*index].to_string());
+                }
+            } else if let Some(quarantine) = quarantine.as_mut() {
+                quarantine.write_record(&record)?;
+            }
+        }
+        for (index, values) in keys_of_kept {
+            referenced.get_mut(&(table.name, table.columns[index])).unwrap().extend(values);
+        }
+        if let Some(mut output) = output {
+            output.flush()?;
+            fs::rename(format!("{file}.valid"), &file)?;
+        }
+        if let Some(mut quarantine) = quarantine {
+            quarantine.flush()?;
+        }
+        summary.push((table.name, violations));
+    }
+    Ok(summary)
+}
+
+fn column_indices(table: &TableInfo, keys: &[Vec<&'static str>]) -> Vec<Vec<usize>> {
+    keys.iter()
+        .map(|key| key.iter().map(|column| column_index(table, column)).collect())
+        .collect()
+}
+
+fn column_index(table: &TableInfo, column: &str) -> usize {
+    table.columns.iter().position(|name| *name == column).unwrap()
+}
+
+fn create(file: &str, delimiter: u8, columns: &[&str]) -> Result<Writer<File>, Box<dyn Error>> {
+    let mut writer = WriterBuilder::new().delimiter(delimiter).has_headers(false).from_path(file)?;
+    writer.write_record(columns)?;
+    Ok(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{parse_fixture, scratch_dir};
+    use crate::tables::{PublicationAuthorRow, PublicationRow, ReferenceRow, Row, VenueRow};
+
+    /// Path of the tsv file of a table in `dir`.
+    fn table_file(dir: &str, table: &str) -> String {
+        let table = dblp_tables().into_iter().find(|info| info.name == table).unwrap();
+        OutputFormat::Tsv.file(&file_in(dir, table.file))
+    }
+
+    /// Rows of a tsv file, without the header.
+    fn rows(file: &str) -> Vec<Vec<String>> {
+        ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(file)
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    fn violations<'a>(summary: &'a [(&'static str, Violations)], table: &str) -> &'a Violations {
+        &summary.iter().find(|(name, _)| *name == table).unwrap().1
+    }
+
+    #[test]
+    fn applies_policy_to_dangling_references() {
+        let dangling = vec![String::from("cite"), String::from("journals/x/Smith20"), String::from("journals/x/Missing")];
+        for policy in [IntegrityPolicy::Drop, IntegrityPolicy::Quarantine, IntegrityPolicy::Keep] {
+            let dir = scratch_dir(&format!("validate_{policy:?}"));
+            parse_fixture("dblp.invalid.xml", &dir, OutputFormat::Tsv, None);
+            let summary = validate_dblp(&dir, OutputFormat::Tsv, policy).unwrap();
+
+            let references = violations(&summary, ReferenceRow::TABLE);
+            assert_eq!(references.dangling, BTreeMap::from([("dest_pub", 1)]), "{policy:?}");
+            let kept = rows(&table_file(&dir, ReferenceRow::TABLE));
+            assert_eq!(kept.contains(&dangling), policy == IntegrityPolicy::Keep, "{policy:?}");
+            // Citations of publications in the dump stay
+            assert!(kept.contains(&vec![String::from("cite"), String::from("conf/y/Smith19"), String::from("journals/x/Smith20")]));
+            let quarantined = table_file(&format!("{dir}/{QUARANTINE_DIR}"), ReferenceRow::TABLE);
+            match policy {
+                IntegrityPolicy::Quarantine => assert_eq!(rows(&quarantined), vec![dangling.clone()]),
+                _ => assert!(!Path::new(&quarantined).exists(), "{policy:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn drops_duplicate_keys() {
+        let dir = scratch_dir("validate_duplicates");
+        parse_fixture("dblp.invalid.xml", &dir, OutputFormat::Tsv, None);
+        let summary = validate_dblp(&dir, OutputFormat::Tsv, IntegrityPolicy::Drop).unwrap();
+
+        assert_eq!(violations(&summary, PublicationRow::TABLE).duplicate_keys, 1);
+        // The authorship of the duplicate repeats the publication key and position of the first record
+        assert_eq!(violations(&summary, PublicationAuthorRow::TABLE).duplicate_keys, 1);
+        let publications: Vec<(String, String)> = rows(&table_file(&dir, PublicationRow::TABLE))
+            .into_iter()
+            .map(|row| (row[0].clone(), row[2].clone()))
+            .collect();
+        assert_eq!(
+            publications,
+            vec![
+                (String::from("journals/x/Smith20"), String::from("A Paper.")),
+                (String::from("conf/y/Smith19"), String::from("Another Paper.")),
+            ]
+        );
+    }
+
+    #[test]
+    fn cascades_drops_to_child_tables() {
+        let dir = scratch_dir("validate_cascade");
+        parse_fixture("dblp.invalid.xml", &dir, OutputFormat::Tsv, None);
+        // Without its venue, Smith19 dangles and takes its authorship and citation along
+        let venues = table_file(&dir, VenueRow::TABLE);
+        let kept = rows(&venues);
+        let columns: Vec<&str> = VenueRow::columns().into_iter().map(|(name, _)| name).collect();
+        let mut writer = create(&venues, b'\t', &columns).unwrap();
+        for row in kept.into_iter().filter(|row| row[1] != "Y") {
+            writer.write_record(&row).unwrap();
+        }
+        writer.flush().unwrap();
+        let summary = validate_dblp(&dir, OutputFormat::Tsv, IntegrityPolicy::Drop).unwrap();
+
+        assert_eq!(violations(&summary, PublicationRow::TABLE).dangling, BTreeMap::from([("venue_id", 1)]));
+        assert_eq!(violations(&summary, PublicationAuthorRow::TABLE).dangling, BTreeMap::from([("publication_key", 1)]));
+        assert_eq!(violations(&summary, ReferenceRow::TABLE).dangling, BTreeMap::from([("origin_pub", 1), ("dest_pub", 1)]));
+        let publication_keys = |table: &str, column: usize| -> Vec<String> {
+            rows(&table_file(&dir, table)).into_iter().map(|row| row[column].clone()).collect()
+        };
+        assert_eq!(publication_keys(PublicationRow::TABLE, 0), vec!["journals/x/Smith20"]);
+        assert_eq!(publication_keys(PublicationAuthorRow::TABLE, 0), vec!["journals/x/Smith20"]);
+        assert!(publication_keys(ReferenceRow::TABLE, 1).is_empty());
+    }
+}